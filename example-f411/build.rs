@@ -0,0 +1,3 @@
+fn main() {
+    icecompr::build::embed("../icebreaker-soc/build/icebreaker/gateware/icebreaker.bin", "bitstream");
+}
@@ -24,8 +24,10 @@ use litex_pac::register::MemoryInterface;
 use litex_pac::{ctrl, leds};
 use litex_pac::{read_reg, write_reg};
 use stm32f4xx_hal::gpio::Speed;
+use icecompr::{ice40, AnySource, BitstreamSource};
 
-const BITSTREAM: &[u8] = include_bytes!("../../icebreaker-soc/build/icebreaker/gateware/icebreaker.bin");
+// Compressed by build.rs
+include!(concat!(env!("OUT_DIR"), "/bitstream.rs"));
 
 struct SpiMemoryInterface<SPI, CS, RESET, DELAY> {
     spi: SPI,
@@ -42,8 +44,37 @@ where
     DELAY: stm32f4xx_hal::hal::blocking::delay::DelayUs<u32>,
     SPI::Error: core::fmt::Debug,
 {
-    pub fn upload_bitstream(&mut self, bitstream: &[u8]) {
+    /// Uploads a bitstream
+    ///
+    /// `open_source` is called twice: the whole image is decoded and checked
+    /// first, and only sent to the FPGA if it is valid. A corrupt image
+    /// leaves the FPGA held in reset. Compressed images with an integrity
+    /// trailer are also checked against their CRC by the decoder.
+    pub fn upload_bitstream<S>(&mut self, open_source: impl Fn() -> S)
+    where
+        S: BitstreamSource,
+        S::Error: core::fmt::Display,
+    {
         self.creset.set_low().ok();
+
+        let mut parser = ice40::Parser::for_device(ice40::Device::Up5k);
+        let mut source = open_source();
+        let mut buf = [0; 64];
+        loop {
+            let n = match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => self.hold_in_reset(e),
+            };
+            if let Err(e) = parser.push(&buf[..n]) {
+                self.hold_in_reset(e);
+            }
+        }
+        // The FPGA only starts with the clocks after the wakeup command
+        if let Err(e) = parser.finish() {
+            self.hold_in_reset(e);
+        }
+
         self.cs.set_low().ok();
         self.delay.delay_us(10); // >=200ns
         self.creset.set_high().ok();
@@ -54,11 +85,25 @@ where
         self.spi.write(&[0]).unwrap();
         self.cs.set_low().ok();
 
-        self.spi.write(bitstream).unwrap();
+        let mut source = open_source();
+        loop {
+            let n = match source.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => self.hold_in_reset(e),
+            };
+            self.spi.write(&buf[..n]).unwrap();
+        }
         self.spi.write(&[0; 6]).unwrap();
 
         self.cs.set_high().ok();
     }
+
+    /// Keeps a corrupt image from starting
+    fn hold_in_reset(&mut self, error: impl core::fmt::Display) -> ! {
+        self.creset.set_low().ok();
+        panic!("Corrupt bitstream: {}", error);
+    }
 }
 
 impl<SPI, CS, RESET, DELAY> MemoryInterface for SpiMemoryInterface<SPI, CS, RESET, DELAY>
@@ -152,7 +197,8 @@ fn main() -> ! {
         litex_pac::register::set_memory_interface(&mut *ptr);
     }
 
-    mem_interface.upload_bitstream(BITSTREAM);
+    let open_source = || AnySource::detect(bitstream::DATA).unwrap();
+    mem_interface.upload_bitstream(open_source);
 
     let ctrl = ctrl::CTRL::take().unwrap();
     let leds = leds::LEDS::take().unwrap();
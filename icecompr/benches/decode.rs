@@ -0,0 +1,131 @@
+use icecompr::{compress, Decoder};
+use std::time::Instant;
+
+// Pseudo-random data resembling an iCE40 bitstream: long zero runs
+// with sparse set bits and occasional densely populated regions
+fn bitstream_like(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut data = vec![0u8; len];
+    let mut i = 0;
+    while i < len {
+        let run = (next() % 512) as usize;
+        let dense = (next() % 64) as usize;
+        i += run;
+        for byte in data.iter_mut().skip(i).take(dense) {
+            *byte = next() as u8;
+        }
+        i += dense;
+        if i < len {
+            data[i] = 1 << (next() % 8);
+        }
+        i += 1;
+    }
+    data
+}
+
+fn decode<const N: usize>(compressed: &[u8], buf: &mut [u8], expected_len: usize) {
+    let mut decoder = Decoder::<N>::with_capacity(compressed);
+    let mut total = 0;
+    loop {
+        let n = decoder.read(buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        total += n;
+    }
+    assert_eq!(total, expected_len);
+}
+
+/// Bit-at-a-time decoder working like the original implementation, as a
+/// baseline for the speedup
+fn decode_reference(compressed: &[u8]) -> Vec<u8> {
+    assert!(compressed.starts_with(b"ICECOMPR"));
+    let mut position = 64;
+    let mut read_bit = || {
+        let bit = (compressed[position / 8] >> (7 - position % 8)) & 1;
+        position += 1;
+        bit
+    };
+
+    let mut output = Vec::new();
+    let mut byte = 0u8;
+    let mut count = 0;
+    let mut write_bit = |bit: u8| {
+        byte = (byte << 1) | bit;
+        count += 1;
+        if count == 8 {
+            output.push(byte);
+            count = 0;
+        }
+    };
+
+    loop {
+        let mut prefix = 0;
+        while prefix < 5 && read_bit() == 0 {
+            prefix += 1;
+        }
+        let width = [2, 5, 8, 6, 23, 23][prefix];
+        let mut value = 0u32;
+        for _ in 0..width {
+            value = (value << 1) | read_bit() as u32;
+        }
+
+        for _ in 0..value {
+            let bit = if prefix == 3 { read_bit() } else { 0 };
+            write_bit(bit);
+        }
+        if prefix == 5 {
+            break;
+        }
+        write_bit(1);
+    }
+    output
+}
+
+fn bench(name: &str, len: usize, f: impl Fn()) -> f64 {
+    const ITERATIONS: u32 = 20;
+
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+    let throughput = len as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!("{:<42} {:>10.3?} {:>8.1} MB/s", name, elapsed, throughput);
+    throughput
+}
+
+fn main() {
+    let data = bitstream_like(104_090); // iCE40 UP5K bitstream size
+    let mut compressed = vec![0u8; data.len() * 2];
+    let n = compress(&data, &mut compressed).unwrap();
+    let compressed = &compressed[..n];
+    println!("{} bytes compressed to {} bytes", data.len(), compressed.len());
+    assert_eq!(decode_reference(compressed), data);
+
+    let reference = bench("reference, bit at a time", data.len(), || {
+        assert_eq!(decode_reference(compressed).len(), data.len())
+    });
+
+    bench("decode, 16-byte buffer, 64-byte reads", data.len(), || {
+        decode::<16>(compressed, &mut [0; 64], data.len())
+    });
+    bench("decode, 16-byte buffer, 4096-byte reads", data.len(), || {
+        decode::<16>(compressed, &mut [0; 4096], data.len())
+    });
+    bench("decode, 256-byte buffer, 64-byte reads", data.len(), || {
+        decode::<256>(compressed, &mut [0; 64], data.len())
+    });
+    let fastest = bench("decode, 256-byte buffer, 4096-byte reads", data.len(), || {
+        decode::<256>(compressed, &mut [0; 4096], data.len())
+    });
+    println!("speedup over the reference: {:.1}x", fastest / reference);
+}
@@ -0,0 +1,45 @@
+#![no_main]
+// Replay the regression corpus with `cargo +nightly fuzz run decode corpus/decode -- -runs=0`
+use libfuzzer_sys::fuzz_target;
+use icecompr::{scan, Decoder, StreamDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = [0u8; 64];
+
+    let mut decoder = Decoder::new(data);
+    let mut total = 0;
+    let result = loop {
+        match decoder.read(&mut buf) {
+            Ok(0) => break Ok(total),
+            Ok(n) => total += n,
+            Err(e) => break Err(e),
+        }
+    };
+
+    // The pre-scan must agree with the decoder
+    let scanned = scan(data).map(|info| info.decompressed_len);
+    assert_eq!(scanned, result);
+
+    // Feed the same data in small chunks to exercise resumption at chunk boundaries
+    let mut decoder = StreamDecoder::new();
+    'outer: for chunk in data.chunks(7) {
+        let mut chunk = chunk;
+        while !chunk.is_empty() {
+            let n = decoder.feed(chunk);
+            chunk = &chunk[n..];
+            loop {
+                match decoder.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(_) => break 'outer,
+                }
+            }
+        }
+    }
+    decoder.finish();
+    while let Ok(n) = decoder.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+    }
+});
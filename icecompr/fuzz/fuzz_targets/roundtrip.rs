@@ -0,0 +1,31 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use icecompr::{compress, scan, Decoder, Encoder};
+
+fn decompress(compressed: &[u8]) -> Vec<u8> {
+    let mut decoder = Decoder::new(compressed);
+    let mut decompressed = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        let n = decoder.read(&mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        decompressed.extend_from_slice(&buf[..n]);
+    }
+    decompressed
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut compressed = vec![0u8; data.len() * 2 + 16];
+    let n = compress(data, &mut compressed).unwrap();
+    assert_eq!(decompress(&compressed[..n]), data);
+
+    // Same stream with the integrity trailer
+    let mut encoder = Encoder::with_trailer(data);
+    let mut compressed = vec![0u8; data.len() * 2 + 32];
+    let n = encoder.read(&mut compressed).unwrap();
+    assert!(encoder.is_finished());
+    assert_eq!(decompress(&compressed[..n]), data);
+    assert!(scan(&compressed[..n]).unwrap().crc32.is_some());
+});
@@ -0,0 +1,152 @@
+use crate::{Decoder, SinkError};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Destination of decompressed data for `Decoder::decode_to_async`
+// Executors on single-core MCUs do not need `Send` futures
+#[allow(async_fn_in_trait)]
+pub trait AsyncSink {
+    type Error;
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Future that returns `Pending` once so the executor can run other tasks
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a, const N: usize> Decoder<'a, N> {
+    /// Decompresses the rest of the stream into `sink`
+    ///
+    /// Data is decoded in chunks of up to `N` bytes, the task yields to the
+    /// executor after every chunk. Choose `N` to balance the latency of other
+    /// tasks against the scheduling overhead. Returns the number of bytes
+    /// written.
+    pub async fn decode_to_async<S: AsyncSink>(&mut self, sink: &mut S) -> Result<usize, SinkError<S::Error>> {
+        let mut buf = [0; N];
+        let mut total = 0;
+        loop {
+            let n = self.read(&mut buf)?;
+            if n == 0 {
+                return Ok(total);
+            }
+            sink.write(&buf[..n]).await.map_err(SinkError::Sink)?;
+            total += n;
+            YieldNow(false).await;
+        }
+    }
+
+    /// Decompresses the rest of the stream into an async SPI bus
+    ///
+    /// The last transfer may still be in progress, call `flush` before
+    /// releasing chip select.
+    #[cfg(feature = "embedded-hal-async")]
+    pub async fn decode_to_spi_async<SPI>(&mut self, spi: &mut SPI) -> Result<usize, SinkError<SPI::Error>>
+    where
+        SPI: embedded_hal_async::spi::SpiBus<u8>,
+    {
+        self.decode_to_async(&mut SpiSink(spi)).await
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+struct SpiSink<'s, SPI>(&'s mut SPI);
+
+#[cfg(feature = "embedded-hal-async")]
+impl<SPI: embedded_hal_async::spi::SpiBus<u8>> AsyncSink for SpiSink<'_, SPI> {
+    type Error = SPI::Error;
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), SPI::Error> {
+        self.0.write(data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::{compress, sample_data};
+    use core::task::{RawWaker, RawWakerVTable, Waker};
+    use std::vec::Vec;
+
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+
+    /// Polls `future` to completion, returns its output and the number of
+    /// times it was `Pending`
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut future = core::pin::pin!(future);
+        let mut pending = 0;
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return (output, pending),
+                Poll::Pending => pending += 1,
+            }
+        }
+    }
+
+    /// Sink that collects the chunks and fails on write number `fail_at`
+    struct VecSink {
+        chunks: Vec<Vec<u8>>,
+        fail_at: usize,
+    }
+
+    impl AsyncSink for VecSink {
+        type Error = usize;
+
+        async fn write(&mut self, data: &[u8]) -> Result<(), usize> {
+            if self.chunks.len() == self.fail_at {
+                return Err(self.fail_at);
+            }
+            self.chunks.push(data.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn yields_between_chunks() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+        let mut sink = VecSink { chunks: Vec::new(), fail_at: usize::MAX };
+        let mut decoder = Decoder::<64>::with_capacity(&compressed);
+        let (result, pending) = block_on(decoder.decode_to_async(&mut sink));
+        assert_eq!(result, Ok(data.len()));
+        assert_eq!(pending, sink.chunks.len());
+        assert!(sink.chunks.iter().all(|chunk| chunk.len() <= 64));
+        assert_eq!(sink.chunks.concat(), data);
+    }
+
+    #[test]
+    fn sink_error() {
+        let compressed = compress(&sample_data(), false);
+        let mut sink = VecSink { chunks: Vec::new(), fail_at: 3 };
+        let mut decoder = Decoder::<64>::with_capacity(&compressed);
+        let (result, pending) = block_on(decoder.decode_to_async(&mut sink));
+        assert_eq!(result, Err(SinkError::Sink(3)));
+        assert_eq!(pending, 3);
+        assert_eq!(sink.chunks.len(), 3);
+    }
+}
@@ -0,0 +1,169 @@
+use icecompr::{ice40, scan, Command, Commands, Decoder, Encoder};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+const USAGE: &str = "\
+Usage: icecompr <command> [arguments]
+
+Commands:
+    compress [--trailer] [INPUT [OUTPUT]]
+                                     Compress INPUT into OUTPUT, --trailer appends
+                                     the length and CRC-32 for the decoder to check
+    decompress [INPUT [OUTPUT]]      Decompress INPUT into OUTPUT
+    verify INPUT [COMPRESSED]        Check that COMPRESSED (or INPUT compressed
+                                     on the fly) decompresses back to INPUT
+    info [INPUT]                     Print sizes and command statistics
+
+INPUT and OUTPUT default to stdin and stdout, \"-\" selects them explicitly.";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn read_input(path: Option<&str>) -> Result<Vec<u8>> {
+    match path {
+        None | Some("-") => {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            Ok(data)
+        }
+        Some(path) => fs::read(path).map_err(|e| format!("{}: {}", path, e).into()),
+    }
+}
+
+fn write_output(path: Option<&str>, data: &[u8]) -> Result<()> {
+    match path {
+        None | Some("-") => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(data)?;
+            stdout.flush()?;
+            Ok(())
+        }
+        Some(path) => fs::write(path, data).map_err(|e| format!("{}: {}", path, e).into()),
+    }
+}
+
+fn compress(input: &[u8], trailer: bool) -> Result<Vec<u8>> {
+    let mut encoder = if trailer { Encoder::with_trailer(input) } else { Encoder::new(input) };
+    let mut compressed = Vec::new();
+    let mut buf = [0; 4096];
+    while !encoder.is_finished() {
+        let n = encoder.read(&mut buf)?;
+        compressed.extend_from_slice(&buf[..n]);
+    }
+    Ok(compressed)
+}
+
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    Decoder::<256>::with_capacity(compressed).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+fn verify(input: &[u8], compressed: &[u8]) -> Result<()> {
+    let decompressed = decompress(compressed)?;
+    if let Some(offset) = input.iter().zip(&decompressed).position(|(a, b)| a != b) {
+        return Err(format!("data differs at byte {}", offset).into());
+    }
+    if input.len() != decompressed.len() {
+        return Err(format!("length differs: {} bytes expected, {} bytes decompressed", input.len(), decompressed.len()).into());
+    }
+    Ok(())
+}
+
+fn info(compressed: &[u8]) -> Result<()> {
+    // Zero runs grouped by the width of their length field
+    let classes = [2, 5, 8, 23];
+    let mut zero_runs = [0usize; 4];
+    let mut raw_commands = 0;
+    let mut raw_bits = 0;
+    let mut output_bits = 0;
+    let mut commands = Commands::new(compressed);
+    let mut command_bits = 0;
+    let mut offset = commands.bit_offset();
+    while let Some(command) = commands.next() {
+        let command = command?;
+        match command {
+            Command::ZeroRun { width, .. } => {
+                let class = classes.iter().position(|&class| class == width).unwrap_or(classes.len() - 1);
+                zero_runs[class] += 1;
+            }
+            Command::Raw { len, .. } => {
+                raw_commands += 1;
+                raw_bits += len as u64;
+            }
+            Command::Finish { .. } => {}
+        }
+        output_bits += command.output_bits();
+        command_bits += commands.bit_offset() - offset;
+        offset = commands.bit_offset();
+    }
+
+    let decompressed_len = output_bits / 8;
+    let crc32 = scan(compressed)?.crc32;
+    println!("header:        ICECOMPR");
+    println!("compressed:    {} bytes", compressed.len());
+    println!("decompressed:  {} bytes", decompressed_len);
+    if decompressed_len > 0 {
+        println!("ratio:         {:.2}%", compressed.len() as f64 * 100.0 / decompressed_len as f64);
+    }
+    println!("commands:      {} ({} bits)", zero_runs.iter().sum::<usize>() + raw_commands + 1, command_bits);
+    for (&width, &count) in classes.iter().zip(&zero_runs) {
+        println!("  zero run/{:<2}  {}", width, count);
+    }
+    println!("  raw          {} ({} data bits, {:.2}% of output)", raw_commands, raw_bits, raw_bits as f64 * 100.0 / output_bits.max(1) as f64);
+    match crc32 {
+        Some(crc) => println!("trailer:       CRC-32 {:#010x}, verified", crc),
+        None => println!("trailer:       none"),
+    }
+
+    match ice40::parse(&decompress(compressed)?) {
+        Ok(info) => match info.device {
+            Some(device) => println!("bitstream:     {:?}, {} CRC checks", device, info.crc_checks),
+            None => println!("bitstream:     no CRAM data"),
+        },
+        Err(e) => println!("bitstream:     {}", e),
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    match args.as_slice() {
+        ["compress", "--trailer", rest @ ..] if rest.len() <= 2 => {
+            let input = read_input(rest.first().copied())?;
+            write_output(rest.get(1).copied(), &compress(&input, true)?)
+        }
+        ["compress", rest @ ..] if rest.len() <= 2 => {
+            let input = read_input(rest.first().copied())?;
+            write_output(rest.get(1).copied(), &compress(&input, false)?)
+        }
+        ["decompress", rest @ ..] if rest.len() <= 2 => {
+            let compressed = read_input(rest.first().copied())?;
+            write_output(rest.get(1).copied(), &decompress(&compressed)?)
+        }
+        ["verify", input] => {
+            let input = read_input(Some(input))?;
+            verify(&input, &compress(&input, false)?)
+        }
+        ["verify", input, compressed] => {
+            let input = read_input(Some(input))?;
+            let compressed = read_input(Some(compressed))?;
+            verify(&input, &compressed)
+        }
+        ["info", rest @ ..] if rest.len() <= 1 => info(&read_input(rest.first().copied())?),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("icecompr: {}", e);
+        process::exit(1);
+    }
+}
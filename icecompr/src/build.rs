@@ -0,0 +1,67 @@
+//! Helpers for build scripts
+//!
+//! Add `icecompr` to `[build-dependencies]` with the `std` feature and call
+//! `embed` from `build.rs`.
+
+use crate::{Crc32, Encoder};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Compresses a file into `OUT_DIR` and generates a Rust module to include it
+///
+/// For `embed("icebreaker.bin", "bitstream")` the generated
+/// `$OUT_DIR/bitstream.rs` defines the module `bitstream` with `DATA` (the
+/// compressed image), `LEN` (the decompressed length) and `CRC32` (the CRC of
+/// the decompressed data), so the firmware only needs
+/// `include!(concat!(env!("OUT_DIR"), "/bitstream.rs"));`.
+///
+/// The image carries an integrity trailer, so the decoder itself detects a
+/// corrupted flash.
+///
+/// The build is rerun when the file changes. Errors panic and fail the
+/// build, as usual for build scripts.
+pub fn embed(path: impl AsRef<Path>, name: &str) {
+    let path = path.as_ref();
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let data = fs::read(path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+
+    let mut encoder = Encoder::with_trailer(&data);
+    let mut compressed = Vec::new();
+    let mut buf = [0; 4096];
+    while !encoder.is_finished() {
+        let n = encoder.read(&mut buf).unwrap_or_else(|e| panic!("cannot compress {}: {}", path.display(), e));
+        compressed.extend_from_slice(&buf[..n]);
+    }
+
+    let mut crc = Crc32::new();
+    crc.update(&data);
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is not set, embed must be called from a build script"));
+    let compressed_path = out_dir.join(format!("{}.cmp", name));
+    write(&compressed_path, &compressed);
+
+    let code = format!(
+        "/// Compressed `{source}`
+pub mod {name} {{
+    /// Compressed image with an integrity trailer
+    pub const DATA: &[u8] = include_bytes!({compressed:?});
+    /// Length of the decompressed image
+    pub const LEN: usize = {len};
+    /// CRC-32 of the decompressed image
+    pub const CRC32: u32 = {crc:#010x};
+}}
+",
+        source = path.display(),
+        name = name,
+        compressed = compressed_path,
+        len = data.len(),
+        crc = crc.finish(),
+    );
+    write(&out_dir.join(format!("{}.rs", name)), code.as_bytes());
+}
+
+fn write(path: &Path, data: &[u8]) {
+    fs::write(path, data).unwrap_or_else(|e| panic!("cannot write {}: {}", path.display(), e));
+}
@@ -0,0 +1,140 @@
+use crate::{Checksum, Decoder, DecoderError, DecoderErrorKind, DecoderReader, DecoderState, PartialByte};
+
+/// Saved position of a `Decoder`
+///
+/// Restoring a checkpoint makes the decoder produce the same output again,
+/// starting from `output_offset()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint {
+    state: DecoderState,
+    partial: PartialByte,
+    commands: usize,
+    checksum: Option<Checksum>,
+    input_bit_offset: usize,
+    output_offset: usize,
+}
+
+impl Checkpoint {
+    /// Offset in the decompressed data the decoding resumes from
+    pub fn output_offset(&self) -> usize {
+        self.output_offset
+    }
+
+    /// Offset in the compressed data the decoding resumes from, in bits
+    pub fn input_bit_offset(&self) -> usize {
+        self.input_bit_offset
+    }
+}
+
+impl<'a, const N: usize> Decoder<'a, N> {
+    /// Saves the current position
+    ///
+    /// Returns `None` while decoded data is still held in the output buffer,
+    /// which can only happen after reads shorter than `N` bytes.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        let writer = &self.core.writer;
+        if writer.start != writer.end {
+            return None;
+        }
+
+        Some(Checkpoint {
+            state: self.core.state,
+            partial: writer.partial,
+            commands: self.core.commands,
+            checksum: self.core.checksum,
+            input_bit_offset: self.reader.bit_offset(),
+            output_offset: self.core.output_offset,
+        })
+    }
+
+    /// Moves the decoder back (or forward) to a saved position
+    ///
+    /// The checkpoint must come from a decoder for the same compressed data.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) -> Result<(), DecoderError> {
+        let offset = checkpoint.input_bit_offset;
+        let invalid = DecoderError::new(DecoderErrorKind::InvalidState, offset, checkpoint.commands, checkpoint.output_offset);
+        if offset > self.compressed.len() * 8 {
+            return Err(invalid);
+        }
+
+        let mut reader = DecoderReader::new(&self.compressed[offset / 8..]);
+        reader.fed_bits = offset / 8 * 8;
+        reader.skip_bits(offset % 8).ok_or(invalid)?;
+
+        self.reader = reader;
+        self.core.state = checkpoint.state;
+        self.core.commands = checkpoint.commands;
+        self.core.checksum = checkpoint.checksum;
+        self.core.writer.start = 0;
+        self.core.writer.end = 0;
+        self.core.writer.partial = checkpoint.partial;
+        self.core.output_offset = checkpoint.output_offset;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::{compress, read_to_end, sample_data};
+
+    #[test]
+    fn restore_replays_output() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+        let mut decoder = Decoder::<16>::with_capacity(&compressed);
+        assert_eq!(decoder.read(&mut [0; 100]), Ok(100));
+        let checkpoint = decoder.checkpoint().unwrap();
+        assert_eq!(checkpoint.output_offset(), 100);
+
+        assert_eq!(read_to_end(&mut decoder, 64).as_deref(), Ok(&data[100..]));
+        decoder.restore(&checkpoint).unwrap();
+        assert_eq!(read_to_end(&mut decoder, 64).as_deref(), Ok(&data[checkpoint.output_offset()..]));
+    }
+
+    #[test]
+    fn buffered_output() {
+        let compressed = compress(&sample_data(), false);
+        let mut decoder = Decoder::<16>::with_capacity(&compressed);
+        assert_eq!(decoder.read(&mut [0; 1]), Ok(1));
+        assert_eq!(decoder.checkpoint(), None);
+
+        // Reading the rest of the buffer makes it possible again
+        assert_eq!(decoder.read(&mut [0; 15]), Ok(15));
+        assert_eq!(decoder.checkpoint().map(|checkpoint| checkpoint.output_offset()), Some(16));
+    }
+
+    #[test]
+    fn trailer_after_restore() {
+        let data = sample_data();
+        let mut compressed = compress(&data, true);
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+
+        let mut decoder = Decoder::<16>::with_capacity(&compressed);
+        assert_eq!(decoder.read(&mut [0; 100]), Ok(100));
+        let checkpoint = decoder.checkpoint().unwrap();
+        let error = read_to_end(&mut decoder, 64).unwrap_err();
+        assert_eq!(error.kind(), DecoderErrorKind::ChecksumMismatch);
+
+        // The checksum of the first 100 bytes is restored as well
+        decoder.restore(&checkpoint).unwrap();
+        assert_eq!(read_to_end(&mut decoder, 64), Err(error));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let compressed = compress(&sample_data(), false);
+        let mut decoder = Decoder::<16>::with_capacity(&compressed);
+        assert_eq!(decoder.read(&mut [0; 1000]), Ok(1000));
+        let checkpoint = decoder.checkpoint().unwrap();
+
+        // Checkpoint of a decoder for longer data
+        let mut decoder = Decoder::<16>::with_capacity(&compressed[..16]);
+        let error = decoder.restore(&checkpoint).unwrap_err();
+        assert_eq!(error.kind(), DecoderErrorKind::InvalidState);
+        assert_eq!(error.bit_offset(), checkpoint.input_bit_offset());
+    }
+}
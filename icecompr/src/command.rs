@@ -0,0 +1,187 @@
+use crate::{trailer, DecoderError, DecoderErrorKind, DecoderReader};
+
+/// Single command of a compressed stream
+///
+/// Every command except `Finish` ends its output with a one bit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// `len` zero bits followed by a one bit
+    ///
+    /// `width` is the size of the length field the stream used for it, 2, 5,
+    /// 8 or 23 bits.
+    ZeroRun { len: u32, width: u8 },
+    /// `len` bits copied from the stream (right-aligned in `bits`) followed by a one bit
+    Raw { bits: u64, len: u32 },
+    /// `zeros` zero bits, then the end of the stream
+    Finish { zeros: u32 },
+}
+
+impl Command {
+    /// Number of decompressed bits produced by the command
+    pub fn output_bits(&self) -> u64 {
+        match *self {
+            Command::ZeroRun { len, .. } => len as u64 + 1,
+            Command::Raw { len, .. } => len as u64 + 1,
+            Command::Finish { zeros } => zeros as u64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CommandsState {
+    Header,
+    Commands,
+    Finished,
+    Done,
+}
+
+/// Iterator over the commands of a compressed stream
+///
+/// Stops after the `Finish` command, or after yielding the first error.
+/// Errors are the same `Decoder` would return on the same data, except that
+/// the integrity trailer is not verified.
+pub struct Commands<'a> {
+    reader: DecoderReader<'a>,
+    state: CommandsState,
+    commands: usize,
+    output_bits: u64,
+}
+
+impl<'a> Commands<'a> {
+    pub fn new(compressed: &'a [u8]) -> Self {
+        Self {
+            reader: DecoderReader::new(compressed),
+            state: CommandsState::Header,
+            commands: 0,
+            output_bits: 0,
+        }
+    }
+
+    /// Offset of the next command in the compressed data, in bits
+    pub fn bit_offset(&self) -> usize {
+        self.reader.bit_offset()
+    }
+
+    fn error(&mut self, kind: DecoderErrorKind) -> DecoderError {
+        self.state = CommandsState::Done;
+        DecoderError::new(kind, self.reader.bit_offset(), self.commands, (self.output_bits / 8) as usize)
+    }
+
+    fn read_command(&mut self) -> Result<Command, DecoderErrorKind> {
+        let command = match self.reader.read_command_code() {
+            Some((3, _, len)) => {
+                if self.reader.len_bits() < len as usize {
+                    return Err(DecoderErrorKind::UnexpectedEof);
+                }
+                let mut bits = 0u64;
+                let mut remaining = len as usize;
+                while remaining > 0 {
+                    let n = core::cmp::min(remaining, 32);
+                    bits = (bits << n) | self.reader.read_int(n).unwrap_or(0) as u64;
+                    remaining -= n;
+                }
+                Command::Raw { bits, len }
+            }
+            Some((5, _, zeros)) => Command::Finish { zeros },
+            Some((_, width, len)) => Command::ZeroRun { len, width: width as u8 },
+            None => return Err(DecoderErrorKind::UnexpectedEof),
+        };
+        Ok(command)
+    }
+}
+
+impl Iterator for Commands<'_> {
+    type Item = Result<Command, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == CommandsState::Header {
+            if self.reader.len_bits() < 64 {
+                return Some(Err(self.error(DecoderErrorKind::InvalidHeader)));
+            }
+            if let Err(kind) = self.reader.read_header() {
+                return Some(Err(self.error(kind)));
+            }
+            self.state = CommandsState::Commands;
+        }
+
+        match self.state {
+            CommandsState::Commands => {
+                let command = match self.read_command() {
+                    Ok(command) => command,
+                    Err(kind) => return Some(Err(self.error(kind))),
+                };
+                if let Command::Finish { .. } = command {
+                    self.state = CommandsState::Finished;
+                }
+                self.commands += 1;
+                self.output_bits += command.output_bits();
+                Some(Ok(command))
+            }
+            CommandsState::Finished => {
+                // The integrity trailer is skipped, checking it needs the decompressed data
+                if let Err(kind) = trailer::read_trailer(&mut self.reader, true) {
+                    return Some(Err(self.error(kind)));
+                }
+                if self.reader.len_bits() >= 8 {
+                    return Some(Err(self.error(DecoderErrorKind::TrailingData)));
+                }
+                self.state = CommandsState::Done;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::stream;
+    use std::vec::Vec;
+
+    const FINISH: &str = "00000 00000000000000000000000";
+
+    fn collect(compressed: &[u8]) -> Vec<Result<Command, DecoderErrorKind>> {
+        Commands::new(compressed).map(|command| command.map_err(|error| error.kind())).collect()
+    }
+
+    #[test]
+    fn raw_bits() {
+        // Raw runs longer than 32 bits are read in two parts
+        let compressed = stream(&[
+            "0001 000101 10110",
+            "0001 101000 1010101010101010101010101010101010101010",
+            FINISH,
+        ]
+        .concat());
+        assert_eq!(
+            collect(&compressed),
+            [
+                Ok(Command::Raw { bits: 0b10110, len: 5 }),
+                Ok(Command::Raw { bits: 0xaa_aaaa_aaaa, len: 40 }),
+                Ok(Command::Finish { zeros: 0 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_after_error() {
+        let compressed = stream("1 11  0001 010000 1010");
+        let mut commands = Commands::new(&compressed);
+        assert_eq!(commands.next(), Some(Ok(Command::ZeroRun { len: 3, width: 2 })));
+        let error = commands.next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), DecoderErrorKind::UnexpectedEof);
+        assert_eq!(error.command(), 1);
+        assert_eq!(commands.next(), None);
+        assert_eq!(commands.next(), None);
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut compressed = stream(FINISH);
+        compressed.push(0xff);
+        assert_eq!(collect(&compressed), [Ok(Command::Finish { zeros: 0 }), Err(DecoderErrorKind::TrailingData)]);
+    }
+}
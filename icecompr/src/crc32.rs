@@ -0,0 +1,48 @@
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+// Remainders for every 4-bit value, a full byte table costs 1 KiB of flash
+const TABLE: [u32; 16] = {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 4 {
+            crc = if (crc & 1) != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 as used by zlib and Ethernet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { crc: 0xffff_ffff }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc ^= byte as u32;
+            self.crc = (self.crc >> 4) ^ TABLE[(self.crc & 0x0f) as usize];
+            self.crc = (self.crc >> 4) ^ TABLE[(self.crc & 0x0f) as usize];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,343 @@
+use crate::trailer::{self, TRAILER_LEN};
+use core::fmt;
+
+const HEADER: &[u8; 8] = b"ICECOMPR";
+
+// Longest raw data run that fits into the 6-bit length field
+const MAX_RAW_BITS: usize = 63;
+
+// Longest zero run that fits into the 23-bit length field
+const MAX_ZERO_RUN: usize = (1 << 23) - 1;
+
+#[derive(Debug, PartialEq)]
+pub enum EncoderError {
+    ZeroRunTooLong,
+    BufferTooSmall,
+}
+
+impl fmt::Display for EncoderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            EncoderError::ZeroRunTooLong => "run of zero bits is too long to encode",
+            EncoderError::BufferTooSmall => "output buffer is too small",
+        };
+        f.write_str(message)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EncoderState {
+    Header(usize),
+    Started,
+    Finished,
+}
+
+pub struct Encoder<'a> {
+    input: &'a [u8],
+    position: usize,
+    bits: u128,
+    bit_count: u8,
+    state: EncoderState,
+    /// Trailer still to be written after the last command
+    trailer: Option<[u8; TRAILER_LEN]>,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            position: 0,
+            bits: 0,
+            bit_count: 0,
+            state: EncoderState::Header(0),
+            trailer: None,
+        }
+    }
+
+    /// Creates an encoder that appends an integrity trailer
+    ///
+    /// The trailer holds the length and the CRC-32 of `input`, decoders
+    /// check it once the stream is finished. Decoders that do not know
+    /// about the trailer stop after the last command and ignore it.
+    pub fn with_trailer(input: &'a [u8]) -> Self {
+        Self {
+            trailer: Some(trailer::trailer(input)),
+            ..Self::new(input)
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state == EncoderState::Finished && self.bit_count == 0 && self.trailer.is_none()
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, EncoderError> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            match self.state {
+                EncoderState::Header(n) => {
+                    let count = core::cmp::min(HEADER.len() - n, buf.len() - offset);
+                    buf[offset..offset + count].copy_from_slice(&HEADER[n..n + count]);
+                    offset += count;
+
+                    self.state = if n + count == HEADER.len() {
+                        EncoderState::Started
+                    } else {
+                        EncoderState::Header(n + count)
+                    };
+                }
+                EncoderState::Started if self.bit_count < 8 => {
+                    self.encode_command()?;
+                }
+                EncoderState::Finished if self.bit_count == 0 => match self.trailer.take() {
+                    Some(trailer) => {
+                        for byte in trailer {
+                            self.write_int(byte as u32, 8);
+                        }
+                    }
+                    None => break,
+                },
+                EncoderState::Finished if self.bit_count < 8 => {
+                    // Pad the last byte with zeros
+                    self.write_int(0, 8 - self.bit_count);
+                }
+                _ => {
+                    self.bit_count -= 8;
+                    buf[offset] = (self.bits >> self.bit_count) as u8;
+                    offset += 1;
+                }
+            }
+        }
+        Ok(offset)
+    }
+
+    fn len_bits(&self) -> usize {
+        self.input.len() * 8
+    }
+
+    fn input_bit(&self, position: usize) -> bool {
+        (self.input[position / 8] & (0x80 >> (position % 8))) != 0
+    }
+
+    fn write_int(&mut self, value: u32, bits: u8) {
+        self.bits = (self.bits << bits) | (value as u128 & ((1 << bits) - 1));
+        self.bit_count += bits;
+    }
+
+    fn write_zero_run(&mut self, count: usize) {
+        if count < (1 << 2) {
+            self.write_int(0b1, 1);
+            self.write_int(count as u32, 2);
+        } else if count < (1 << 5) {
+            self.write_int(0b01, 2);
+            self.write_int(count as u32, 5);
+        } else if count < (1 << 8) {
+            self.write_int(0b001, 3);
+            self.write_int(count as u32, 8);
+        } else {
+            self.write_int(0b00001, 5);
+            self.write_int(count as u32, 23);
+        }
+    }
+
+    fn zero_run_cost(count: usize) -> usize {
+        if count < (1 << 2) {
+            3
+        } else if count < (1 << 5) {
+            7
+        } else if count < (1 << 8) {
+            11
+        } else {
+            28
+        }
+    }
+
+    fn encode_command(&mut self) -> Result<(), EncoderError> {
+        let len_bits = self.len_bits();
+
+        let mut zeros = 0;
+        while self.position + zeros < len_bits && !self.input_bit(self.position + zeros) {
+            zeros += 1;
+        }
+
+        if self.position + zeros == len_bits {
+            if zeros > MAX_ZERO_RUN {
+                return Err(EncoderError::ZeroRunTooLong);
+            }
+            self.write_int(0b00000, 5);
+            self.write_int(zeros as u32, 23);
+            self.position = len_bits;
+            self.state = EncoderState::Finished;
+            return Ok(());
+        }
+
+        // Check whether a raw data run ending at one of the following one bits
+        // is shorter than the equivalent sequence of zero runs
+        let mut raw_bits = 0;
+        let mut best_savings = 0;
+        let mut zero_run_cost = 0;
+        let mut run = 0;
+        let end = core::cmp::min(self.position + MAX_RAW_BITS + 1, len_bits);
+        for position in self.position..end {
+            if self.input_bit(position) {
+                zero_run_cost += Self::zero_run_cost(run);
+                run = 0;
+
+                let n = position - self.position;
+                let raw_cost = 4 + 6 + n;
+                if zero_run_cost > raw_cost && zero_run_cost - raw_cost >= best_savings {
+                    best_savings = zero_run_cost - raw_cost;
+                    raw_bits = n;
+                }
+            } else {
+                run += 1;
+            }
+        }
+
+        if best_savings > 0 {
+            self.write_int(0b0001, 4);
+            self.write_int(raw_bits as u32, 6);
+            for position in self.position..self.position + raw_bits {
+                let bit = self.input_bit(position);
+                self.write_int(bit as u32, 1);
+            }
+            self.position += raw_bits + 1;
+        } else {
+            if zeros > MAX_ZERO_RUN {
+                return Err(EncoderError::ZeroRunTooLong);
+            }
+            self.write_zero_run(zeros);
+            self.position += zeros + 1;
+        }
+        Ok(())
+    }
+}
+
+pub fn compress(input: &[u8], output: &mut [u8]) -> Result<usize, EncoderError> {
+    let mut encoder = Encoder::new(input);
+    let n = encoder.read(output)?;
+    if encoder.is_finished() {
+        Ok(n)
+    } else {
+        Err(EncoderError::BufferTooSmall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{Command, Commands, Decoder};
+    use std::vec;
+    use std::vec::Vec;
+
+    fn roundtrip(input: &[u8]) -> Vec<u8> {
+        let mut compressed = vec![0; input.len() * 2 + 64];
+        let n = compress(input, &mut compressed).unwrap();
+        compressed.truncate(n);
+
+        let mut decoder = Decoder::new(&compressed);
+        let mut output = vec![0; input.len() + 1];
+        let mut len = 0;
+        loop {
+            match decoder.read(&mut output[len..]).unwrap() {
+                0 => break,
+                count => len += count,
+            }
+        }
+        assert_eq!(&output[..len], input);
+        compressed
+    }
+
+    fn commands(compressed: &[u8]) -> Vec<Command> {
+        Commands::new(compressed).map(Result::unwrap).collect()
+    }
+
+    /// Input with a single one bit after `zeros` zero bits, padded to whole bytes
+    fn zero_run(zeros: usize) -> Vec<u8> {
+        let mut input = vec![0; zeros / 8 + 2];
+        input[zeros / 8] = 0x80 >> (zeros % 8);
+        input
+    }
+
+    #[test]
+    fn empty() {
+        let compressed = roundtrip(&[]);
+        assert_eq!(commands(&compressed), [Command::Finish { zeros: 0 }]);
+    }
+
+    #[test]
+    fn all_zero() {
+        let compressed = roundtrip(&[0; 1000]);
+        assert_eq!(commands(&compressed), [Command::Finish { zeros: 8000 }]);
+    }
+
+    #[test]
+    fn zero_run_boundaries() {
+        // Largest and smallest run of the 2, 5, 8 and 23-bit length fields
+        for &(zeros, width) in &[(0, 2), (3, 2), (4, 5), (31, 5), (32, 8), (255, 8), (256, 23), (10000, 23)] {
+            let compressed = roundtrip(&zero_run(zeros));
+            assert_eq!(commands(&compressed)[0], Command::ZeroRun { len: zeros as u32, width }, "{} zeros", zeros);
+        }
+    }
+
+    #[test]
+    fn raw_runs() {
+        // Dense data is cheaper as raw runs, up to the 6-bit length limit
+        let input: Vec<u8> = (0..64u32).map(|i| (i.wrapping_mul(0x9e) ^ 0x5a) as u8 | 0x01).collect();
+        let compressed = roundtrip(&input);
+        let raw: Vec<u32> = commands(&compressed)
+            .iter()
+            .filter_map(|command| match *command {
+                Command::Raw { len, .. } => Some(len),
+                _ => None,
+            })
+            .collect();
+        assert!(!raw.is_empty());
+        assert!(raw.iter().all(|&len| len as usize <= MAX_RAW_BITS));
+        assert!(raw.contains(&(MAX_RAW_BITS as u32)));
+    }
+
+    #[test]
+    fn partial_last_byte() {
+        // Commands that do not end on a byte boundary, padded with zeros
+        for input in [&[0x80][..], &[0xff], &[0x00, 0x01], &[0x12, 0x34, 0x56]] {
+            let compressed = roundtrip(input);
+            let bits: u64 = commands(&compressed).iter().map(Command::output_bits).sum();
+            assert_eq!(bits, input.len() as u64 * 8);
+        }
+    }
+
+    #[test]
+    fn trailer() {
+        let input: Vec<u8> = (0..=255).collect();
+        let mut compressed = vec![0; 1024];
+        let mut encoder = Encoder::with_trailer(&input);
+        let n = encoder.read(&mut compressed).unwrap();
+        assert!(encoder.is_finished());
+        compressed.truncate(n);
+        assert!(trailer::has_trailer(&compressed));
+
+        let mut output = vec![0; input.len()];
+        let mut decoder = Decoder::new(&compressed);
+        let mut len = 0;
+        while len < output.len() {
+            len += decoder.read(&mut output[len..]).unwrap();
+        }
+        assert_eq!(output, input);
+        assert_eq!(decoder.read(&mut [0; 1]), Ok(0));
+
+        // A corrupted trailer is reported once the data is decoded
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let mut decoder = Decoder::new(&compressed);
+        let error = loop {
+            match decoder.read(&mut output) {
+                Ok(0) => panic!("corrupted trailer not detected"),
+                Ok(_) => {}
+                Err(error) => break error,
+            }
+        };
+        assert_eq!(error.kind(), crate::DecoderErrorKind::ChecksumMismatch);
+    }
+}
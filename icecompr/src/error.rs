@@ -0,0 +1,100 @@
+use core::fmt;
+
+/// Kind of a decoding failure
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecoderErrorKind {
+    InvalidHeader,
+    UnexpectedEof,
+    InvalidState,
+    TrailingData,
+    /// Decompressed length differs from the one in the trailer
+    LengthMismatch,
+    /// CRC of the decompressed data differs from the one in the trailer
+    ChecksumMismatch,
+}
+
+impl fmt::Display for DecoderErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            DecoderErrorKind::InvalidHeader => "invalid ICECOMPR header",
+            DecoderErrorKind::UnexpectedEof => "unexpected end of compressed data",
+            DecoderErrorKind::InvalidState => "decoder is in the error state",
+            DecoderErrorKind::TrailingData => "trailing data after the end of the compressed stream",
+            DecoderErrorKind::LengthMismatch => "decompressed length does not match the trailer",
+            DecoderErrorKind::ChecksumMismatch => "decompressed data does not match the trailer checksum",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Decoding failure together with the position it happened at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecoderError {
+    kind: DecoderErrorKind,
+    bit_offset: usize,
+    command: usize,
+    output_offset: usize,
+}
+
+impl DecoderError {
+    pub(crate) fn new(kind: DecoderErrorKind, bit_offset: usize, command: usize, output_offset: usize) -> Self {
+        Self {
+            kind,
+            bit_offset,
+            command,
+            output_offset,
+        }
+    }
+
+    pub fn kind(&self) -> DecoderErrorKind {
+        self.kind
+    }
+
+    /// Offset in the compressed data where decoding stopped, in bits
+    pub fn bit_offset(&self) -> usize {
+        self.bit_offset
+    }
+
+    /// Index of the command being decoded, counting from the one after the header
+    pub fn command(&self) -> usize {
+        self.command
+    }
+
+    /// Number of decompressed bytes produced before the failure
+    pub fn output_offset(&self) -> usize {
+        self.output_offset
+    }
+}
+
+impl fmt::Display for DecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at bit {} (command {}, output byte {})",
+            self.kind, self.bit_offset, self.command, self.output_offset
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::stream;
+    use crate::Decoder;
+    use std::string::ToString;
+
+    #[test]
+    fn position() {
+        // Zero runs of 3 and 4 bits give 9 output bits, the raw run of 16
+        // bits after them is cut off after 8 bits plus the padding
+        let compressed = stream("1 11  01 00100  0001 010000 10101010");
+        let error = Decoder::new(&compressed).read(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), DecoderErrorKind::UnexpectedEof);
+        assert_eq!(error.bit_offset(), 64 + 3 + 7 + 10);
+        assert_eq!(error.command(), 2);
+        assert_eq!(error.output_offset(), 1);
+        assert_eq!(error.to_string(), "unexpected end of compressed data at bit 84 (command 2, output byte 1)");
+    }
+}
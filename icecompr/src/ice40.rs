@@ -0,0 +1,523 @@
+//! iCE40 bitstream parser
+//!
+//! Checks the structure and the CRC of a configuration image before it is
+//! sent to the FPGA. Data can be pushed in chunks, e.g. straight from
+//! `Decoder::decode_to`.
+
+use core::fmt;
+
+mod bram;
+pub use bram::{BramLocation, BramPatcher, BramWord};
+
+mod multi;
+pub use multi::{build_multi_image, MultiImage, MultiImageError, MAX_IMAGES};
+
+const PREAMBLE: u32 = 0x7eaa_997e;
+
+/// iCE40 device family member
+///
+/// The bitstream carries no part ID, so the device is identified by the
+/// width of the CRAM banks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Device {
+    /// iCE40LP384
+    Lp384,
+    /// iCE40LP1K, iCE40HX1K
+    Hx1k,
+    /// iCE40LP8K, iCE40HX4K, iCE40HX8K
+    Hx8k,
+    /// iCE40UP3K, iCE40UP5K
+    Up5k,
+}
+
+impl Device {
+    fn from_cram_width(width: u32) -> Option<Self> {
+        match width {
+            182 => Some(Device::Lp384),
+            332 => Some(Device::Hx1k),
+            872 => Some(Device::Hx8k),
+            692 => Some(Device::Up5k),
+            _ => None,
+        }
+    }
+}
+
+/// Summary of a well-formed bitstream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitstreamInfo {
+    /// Target device, `None` if the image carries no CRAM data
+    pub device: Option<Device>,
+    /// Number of CRAM configuration bytes
+    pub cram_bytes: usize,
+    /// Number of BRAM initialization bytes
+    pub bram_bytes: usize,
+    /// Internal oscillator frequency range (0 = low, 1 = medium, 2 = high)
+    pub frequency_range: u8,
+    /// Number of CRC checks passed
+    pub crc_checks: usize,
+    /// Whether the `SB_WARMBOOT` primitive is enabled
+    pub warmboot: bool,
+    /// Flash address of the next image if the image ends with a reboot
+    /// instead of waking up the user design
+    pub reboot_address: Option<u32>,
+    /// Length of the image in bytes, up to the wakeup or reboot command
+    pub len: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitstreamErrorKind {
+    MissingPreamble,
+    InvalidCommand,
+    /// CRAM or BRAM data before the bank size is set
+    MissingGeometry,
+    UnknownDevice,
+    WrongDevice,
+    CrcMismatch,
+    UnexpectedEof,
+    TrailingData,
+    /// Header of a multi-image container that does not point to an image
+    NotMultiImage,
+}
+
+impl fmt::Display for BitstreamErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            BitstreamErrorKind::MissingPreamble => "no iCE40 preamble found",
+            BitstreamErrorKind::InvalidCommand => "invalid configuration command",
+            BitstreamErrorKind::MissingGeometry => "configuration data before the bank size",
+            BitstreamErrorKind::UnknownDevice => "unknown device",
+            BitstreamErrorKind::WrongDevice => "bitstream is for a different device",
+            BitstreamErrorKind::CrcMismatch => "CRC mismatch",
+            BitstreamErrorKind::UnexpectedEof => "unexpected end of bitstream",
+            BitstreamErrorKind::TrailingData => "trailing data after the end of the bitstream",
+            BitstreamErrorKind::NotMultiImage => "not a multi-image header",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Bitstream error together with the byte offset of the offending command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitstreamError {
+    kind: BitstreamErrorKind,
+    offset: usize,
+}
+
+impl BitstreamError {
+    pub fn kind(&self) -> BitstreamErrorKind {
+        self.kind
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for BitstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.kind, self.offset)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BitstreamError {}
+
+/// Role of a bitstream byte, as needed for patching
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Token {
+    Other,
+    BramData(BramLocation),
+    /// Payload of a CRC check command
+    CrcHigh,
+    CrcLow,
+    /// Last byte of a CRC reset command
+    CrcReset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParserState {
+    /// Looking for the preamble, the last bytes seen are kept
+    Preamble(u32),
+    Command,
+    Payload { opcode: u8, remaining: u8, value: u32 },
+    /// CRAM or BRAM data of `len` bytes, followed by two zero bytes
+    Data { index: u64, len: u64, bram: bool },
+    /// Only padding is allowed after the wakeup or reboot command
+    Done,
+}
+
+/// Incremental iCE40 bitstream parser
+pub struct Parser {
+    state: ParserState,
+    expected: Option<Device>,
+    offset: usize,
+    command_offset: usize,
+    crc: u16,
+    bank: u8,
+    bank_offset: u32,
+    width: u32,
+    height: u32,
+    boot_address: Option<u32>,
+    info: BitstreamInfo,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: ParserState::Preamble(0),
+            expected: None,
+            offset: 0,
+            command_offset: 0,
+            crc: 0xffff,
+            bank: 0,
+            bank_offset: 0,
+            width: 0,
+            height: 0,
+            boot_address: None,
+            info: BitstreamInfo {
+                device: None,
+                cram_bytes: 0,
+                bram_bytes: 0,
+                frequency_range: 0,
+                crc_checks: 0,
+                warmboot: false,
+                reboot_address: None,
+                len: 0,
+            },
+        }
+    }
+
+    /// Creates a parser that rejects images for other devices
+    pub fn for_device(device: Device) -> Self {
+        Self {
+            expected: Some(device),
+            ..Self::new()
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<(), BitstreamError> {
+        for &byte in data {
+            self.push_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the image is complete
+    pub fn finish(&self) -> Result<BitstreamInfo, BitstreamError> {
+        match self.state {
+            ParserState::Done => Ok(self.info),
+            ParserState::Preamble(_) => Err(self.error_at(BitstreamErrorKind::MissingPreamble, self.offset)),
+            _ => Err(self.error_at(BitstreamErrorKind::UnexpectedEof, self.offset)),
+        }
+    }
+
+    fn error(&self, kind: BitstreamErrorKind) -> BitstreamError {
+        self.error_at(kind, self.command_offset)
+    }
+
+    fn error_at(&self, kind: BitstreamErrorKind, offset: usize) -> BitstreamError {
+        BitstreamError { kind, offset }
+    }
+
+    pub(crate) fn push_byte(&mut self, byte: u8) -> Result<Token, BitstreamError> {
+        let offset = self.offset;
+        self.offset += 1;
+        self.crc = crc16(self.crc, byte);
+        let mut token = Token::Other;
+
+        match self.state {
+            ParserState::Preamble(window) => {
+                // Everything in front of the preamble is ignored by the FPGA
+                let window = (window << 8) | byte as u32;
+                self.state = if window == PREAMBLE {
+                    ParserState::Command
+                } else {
+                    ParserState::Preamble(window)
+                };
+            }
+            ParserState::Command => {
+                self.command_offset = offset;
+                let opcode = byte >> 4;
+                let len = byte & 0x0f;
+                // CRC checks always carry a 16-bit value
+                if len > 4 || (opcode == 2 && len != 2) {
+                    return Err(self.error(BitstreamErrorKind::InvalidCommand));
+                }
+                self.state = ParserState::Payload { opcode, remaining: len, value: 0 };
+            }
+            ParserState::Payload { opcode, remaining, value } => {
+                if opcode == 2 {
+                    token = if remaining == 2 { Token::CrcHigh } else { Token::CrcLow };
+                }
+                self.state = ParserState::Payload {
+                    opcode,
+                    remaining: remaining - 1,
+                    value: (value << 8) | byte as u32,
+                };
+            }
+            ParserState::Data { index, len, bram } => {
+                if bram && index < len {
+                    token = Token::BramData(BramLocation {
+                        bank: self.bank,
+                        bank_offset: self.bank_offset,
+                        width: self.width,
+                        index: index as usize,
+                    });
+                }
+                self.state = if index + 1 < len + 2 {
+                    ParserState::Data { index: index + 1, len, bram }
+                } else {
+                    ParserState::Command
+                };
+            }
+            ParserState::Done => {
+                if byte != 0x00 && byte != 0xff {
+                    return Err(self.error_at(BitstreamErrorKind::TrailingData, offset));
+                }
+            }
+        }
+
+        if let ParserState::Payload { opcode, remaining: 0, value } = self.state {
+            self.state = ParserState::Command;
+            self.execute(opcode, value)?;
+            if (opcode, value) == (0, 0x05) {
+                token = Token::CrcReset;
+            }
+        }
+        Ok(token)
+    }
+
+    fn execute(&mut self, opcode: u8, value: u32) -> Result<(), BitstreamError> {
+        match opcode {
+            0 => match value {
+                0x01 => {
+                    let device = Device::from_cram_width(self.width).ok_or_else(|| self.error(BitstreamErrorKind::UnknownDevice))?;
+                    if self.expected.is_some_and(|expected| expected != device) {
+                        return Err(self.error(BitstreamErrorKind::WrongDevice));
+                    }
+                    self.info.device = Some(device);
+                    self.info.cram_bytes += self.start_data(false)?;
+                }
+                0x03 => self.info.bram_bytes += self.start_data(true)?,
+                0x05 => self.crc = 0xffff,
+                0x06 => self.end(),
+                0x08 => {
+                    // Boot address defaults to the start of the flash
+                    self.info.reboot_address = Some(self.boot_address.unwrap_or(0));
+                    self.end();
+                }
+                _ => return Err(self.error(BitstreamErrorKind::InvalidCommand)),
+            },
+            1 => {
+                if value > 3 {
+                    return Err(self.error(BitstreamErrorKind::InvalidCommand));
+                }
+                self.bank = value as u8;
+            }
+            2 => {
+                // The CRC bytes themselves are included, leaving a zero remainder
+                if self.crc != 0 {
+                    return Err(self.error(BitstreamErrorKind::CrcMismatch));
+                }
+                self.info.crc_checks += 1;
+            }
+            // The payload starts with the SPI read opcode
+            4 => self.boot_address = Some(value & 0x00ff_ffff),
+            5 => self.info.frequency_range = value as u8,
+            6 => self.width = value.saturating_add(1),
+            7 => self.height = value,
+            8 => self.bank_offset = value,
+            9 => self.info.warmboot = (value & 0x20) != 0,
+            _ => return Err(self.error(BitstreamErrorKind::InvalidCommand)),
+        }
+        Ok(())
+    }
+
+    /// Starts a CRAM or BRAM data section, returns its length
+    fn start_data(&mut self, bram: bool) -> Result<usize, BitstreamError> {
+        let len = (self.width as u64 * self.height as u64).div_ceil(8);
+        if len == 0 {
+            return Err(self.error(BitstreamErrorKind::MissingGeometry));
+        }
+        self.state = ParserState::Data { index: 0, len, bram };
+        Ok(len as usize)
+    }
+
+    fn end(&mut self) {
+        self.info.len = self.offset;
+        self.state = ParserState::Done;
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-16-CCITT as used by the iCE40 configuration logic
+pub(crate) fn crc16(crc: u16, byte: u8) -> u16 {
+    let mut crc = crc ^ ((byte as u16) << 8);
+    for _ in 0..8 {
+        crc = if (crc & 0x8000) != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+    }
+    crc
+}
+
+/// Parses a complete bitstream
+pub fn parse(bitstream: &[u8]) -> Result<BitstreamInfo, BitstreamError> {
+    let mut parser = Parser::new();
+    parser.push(bitstream)?;
+    parser.finish()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    /// Length of the BRAM section of `image`
+    pub(crate) const BRAM_BYTES: usize = 128;
+
+    /// Synthetic image builder that keeps track of the CRC
+    pub(crate) struct Image {
+        pub(crate) data: Vec<u8>,
+        crc: u16,
+    }
+
+    impl Image {
+        pub(crate) fn new() -> Self {
+            Self {
+                data: Vec::from(PREAMBLE.to_be_bytes()),
+                crc: 0xffff,
+            }
+        }
+
+        pub(crate) fn push(&mut self, bytes: &[u8]) -> &mut Self {
+            for &byte in bytes {
+                self.data.push(byte);
+                self.crc = crc16(self.crc, byte);
+            }
+            self
+        }
+
+        pub(crate) fn command(&mut self, opcode: u8, value: &[u8]) -> &mut Self {
+            self.push(&[(opcode << 4) | value.len() as u8]).push(value)
+        }
+
+        pub(crate) fn crc_reset(&mut self) -> &mut Self {
+            self.command(0, &[0x05]);
+            self.crc = 0xffff;
+            self
+        }
+
+        pub(crate) fn crc_check(&mut self) -> &mut Self {
+            self.push(&[0x22]);
+            let crc = self.crc.to_be_bytes();
+            self.push(&crc)
+        }
+
+        /// Data section of `width` x `height` bits, followed by two zero bytes
+        pub(crate) fn section(&mut self, data_command: u8, width: u16, height: u16, data: &[u8]) -> &mut Self {
+            self.command(6, &(width - 1).to_be_bytes());
+            self.command(7, &height.to_be_bytes());
+            self.command(8, &[0, 0]);
+            self.command(0, &[data_command]);
+            self.push(data).push(&[0, 0])
+        }
+    }
+
+    /// UP5K image with two CRAM rows, BRAM data `bram` and a CRC check
+    ///
+    /// Ends with a reboot into `reboot_address` if given, with a wakeup
+    /// otherwise.
+    pub(crate) fn image(bram: &[u8; BRAM_BYTES], reboot_address: Option<u32>) -> Vec<u8> {
+        let mut image = Image::new();
+        let cram: Vec<u8> = (0..173).map(|i| i as u8).collect();
+        image.command(5, &[0x01]).crc_reset().command(1, &[0x00]);
+        image.section(0x01, 692, 2, &cram).section(0x03, 256, 4, bram);
+        image.crc_check();
+        match reboot_address {
+            Some(address) => {
+                let address = address.to_be_bytes();
+                image.command(9, &[0x20]).command(4, &[0x03, address[1], address[2], address[3]]).command(0, &[0x08])
+            }
+            None => image.command(0, &[0x06]),
+        };
+        image.data
+    }
+
+    #[test]
+    fn valid_image() {
+        let image = image(&[0x5a; BRAM_BYTES], None);
+        let info = parse(&image).unwrap();
+        assert_eq!(
+            info,
+            BitstreamInfo {
+                device: Some(Device::Up5k),
+                cram_bytes: 173,
+                bram_bytes: BRAM_BYTES,
+                frequency_range: 1,
+                crc_checks: 1,
+                warmboot: false,
+                reboot_address: None,
+                len: image.len(),
+            }
+        );
+
+        // Padding after the wakeup command is ignored
+        let mut padded = image.clone();
+        padded.extend_from_slice(&[0x00, 0xff, 0x00]);
+        assert_eq!(parse(&padded), Ok(info));
+
+        // Data can be pushed in chunks of any size
+        let mut parser = Parser::for_device(Device::Up5k);
+        for chunk in image.chunks(7) {
+            parser.push(chunk).unwrap();
+        }
+        assert_eq!(parser.finish(), Ok(info));
+    }
+
+    #[test]
+    fn reboot() {
+        let info = parse(&image(&[0; BRAM_BYTES], Some(0x123456))).unwrap();
+        assert!(info.warmboot);
+        assert_eq!(info.reboot_address, Some(0x123456));
+    }
+
+    #[test]
+    fn wrong_device() {
+        let mut parser = Parser::for_device(Device::Hx8k);
+        let error = parser.push(&image(&[0; BRAM_BYTES], None)).unwrap_err();
+        assert_eq!(error.kind(), BitstreamErrorKind::WrongDevice);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut image = image(&[0; BRAM_BYTES], None);
+        image[40] ^= 0x10;
+        assert_eq!(parse(&image).unwrap_err().kind(), BitstreamErrorKind::CrcMismatch);
+    }
+
+    #[test]
+    fn truncated() {
+        let image = image(&[0; BRAM_BYTES], None);
+        let error = parse(&image[..image.len() - 1]).unwrap_err();
+        assert_eq!(error.kind(), BitstreamErrorKind::UnexpectedEof);
+        assert_eq!(error.offset(), image.len() - 1);
+
+        assert_eq!(parse(&[0x7e, 0xaa]).unwrap_err().kind(), BitstreamErrorKind::MissingPreamble);
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut image = image(&[0; BRAM_BYTES], None);
+        let len = image.len();
+        image.extend_from_slice(&[0x00, 0x12]);
+        let error = parse(&image).unwrap_err();
+        assert_eq!(error.kind(), BitstreamErrorKind::TrailingData);
+        assert_eq!(error.offset(), len + 1);
+    }
+}
@@ -0,0 +1,232 @@
+use super::{crc16, BitstreamError, BitstreamInfo, Device, Parser, Token};
+
+/// Position of a byte in a BRAM initialization section
+///
+/// A section holds data for one BRAM bank, starting at row `bank_offset`.
+/// Its bits are stored row by row, `width` bits per row, and each byte holds
+/// eight consecutive bits starting with the most significant one. `position`
+/// does this conversion.
+///
+/// Rows and columns are those of the bank as seen by the configuration logic,
+/// not the addresses of the `SB_RAM40_4K` blocks. `word` maps them to the
+/// blocks the same way icestorm's `icepack` and `icebram` do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BramLocation {
+    pub bank: u8,
+    pub bank_offset: u32,
+    /// Number of bits per row
+    pub width: u32,
+    /// Byte index within the section
+    pub index: usize,
+}
+
+impl BramLocation {
+    /// Column and row within the bank of bit `bit` of the byte, 0 being the
+    /// most significant bit
+    pub fn position(&self, bit: u32) -> (u32, u32) {
+        let n = self.index as u64 * 8 + bit as u64;
+        let width = self.width as u64;
+        ((n % width) as u32, self.bank_offset + (n / width) as u32)
+    }
+
+    /// Block word that bit `bit` of the byte belongs to
+    ///
+    /// Every block takes 16 columns of its bank, one per word in a group of
+    /// 16 words, and 256 rows, 16 per group. Returns `None` for bits outside
+    /// of the blocks of `device`. Only the UP5K layout is known so far.
+    pub fn word(&self, device: Device, bit: u32) -> Option<BramWord> {
+        let (column, row) = self.position(bit);
+        if row >= 256 {
+            return None;
+        }
+        Some(BramWord {
+            tile: bram_tile(device, self.bank, column / 16)?,
+            index: row / 16 * 16 + column % 16,
+            bit: row % 16,
+        })
+    }
+}
+
+/// Bit of an `SB_RAM40_4K` block in the 256 x 16 configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BramWord {
+    /// Tile of the block, as in the `.ram_data` sections of `.asc` files
+    pub tile: (u32, u32),
+    /// Word address, 0 to 255
+    pub index: u32,
+    /// Bit within the word, 0 being the least significant
+    pub bit: u32,
+}
+
+/// Tile of block `block` of `bank`, counting from the bottom
+fn bram_tile(device: Device, bank: u8, block: u32) -> Option<(u32, u32)> {
+    match device {
+        // Two columns of 15 blocks, each two tiles high. The block on rows
+        // 15 and 16 belongs to the bottom banks.
+        Device::Up5k => {
+            let x = if bank & 2 == 0 { 6 } else { 19 };
+            let (first, count) = if bank & 1 == 0 { (1, 8) } else { (17, 7) };
+            if block < count {
+                Some((x, first + 2 * block))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Replaces BRAM initialization data of a bitstream on the fly
+///
+/// Every BRAM data byte is passed through the `patch` callback, and the CRC
+/// check commands are rewritten to match the new contents. The original
+/// image is validated like `Parser` does, including its CRCs, so a corrupt
+/// image is not turned into one with valid CRCs.
+pub struct BramPatcher<F> {
+    parser: Parser,
+    patch: F,
+    crc: u16,
+    /// CRC value stored by the current CRC check command
+    crc_value: u16,
+}
+
+impl<F: FnMut(BramLocation, u8) -> u8> BramPatcher<F> {
+    pub fn new(patch: F) -> Self {
+        Self::with_parser(Parser::new(), patch)
+    }
+
+    /// Creates a patcher that rejects images for other devices
+    pub fn for_device(device: Device, patch: F) -> Self {
+        Self::with_parser(Parser::for_device(device), patch)
+    }
+
+    fn with_parser(parser: Parser, patch: F) -> Self {
+        Self {
+            parser,
+            patch,
+            crc: 0xffff,
+            crc_value: 0,
+        }
+    }
+
+    /// Patches the next chunk of the bitstream in place
+    ///
+    /// Chunks can have any size, e.g. the output of each `Decoder::read`
+    /// call. Do not send a chunk to the FPGA if this returns an error.
+    pub fn process(&mut self, data: &mut [u8]) -> Result<(), BitstreamError> {
+        for byte in data {
+            let token = self.parser.push_byte(*byte)?;
+            *byte = match token {
+                Token::BramData(location) => (self.patch)(location, *byte),
+                Token::CrcHigh => {
+                    self.crc_value = self.crc;
+                    (self.crc_value >> 8) as u8
+                }
+                Token::CrcLow => self.crc_value as u8,
+                Token::Other | Token::CrcReset => *byte,
+            };
+
+            self.crc = crc16(self.crc, *byte);
+            if token == Token::CrcReset {
+                self.crc = 0xffff;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the image is complete
+    pub fn finish(&self) -> Result<BitstreamInfo, BitstreamError> {
+        self.parser.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{image, BRAM_BYTES};
+    use super::super::{parse, BitstreamErrorKind};
+    use super::*;
+
+    #[test]
+    fn patch_bram() {
+        let original = image(&[0; BRAM_BYTES], None);
+        let mut patched = original.clone();
+
+        let mut locations = 0;
+        let mut patcher = BramPatcher::for_device(Device::Up5k, |location: BramLocation, byte| {
+            assert_eq!((location.bank, location.bank_offset, location.width), (0, 0, 256));
+            assert_eq!(location.index, locations);
+            locations += 1;
+            byte ^ (location.index as u8 | 0x80)
+        });
+        for chunk in patched.chunks_mut(5) {
+            patcher.process(chunk).unwrap();
+        }
+        let info = patcher.finish().unwrap();
+        assert_eq!(locations, BRAM_BYTES);
+
+        // The result is a valid image with new BRAM contents and CRC
+        assert_eq!(parse(&patched), Ok(info));
+        assert_eq!(info.crc_checks, 1);
+        let expected = image(&core::array::from_fn(|i| i as u8 | 0x80), None);
+        assert_eq!(patched, expected);
+        assert_ne!(patched[patched.len() - 4..], original[original.len() - 4..]);
+    }
+
+    #[test]
+    fn corrupt_input() {
+        // The CRC of the original image is checked before it is rewritten
+        let mut data = image(&[0; BRAM_BYTES], None);
+        data[30] ^= 1;
+        let mut patcher = BramPatcher::new(|_, byte| byte);
+        let error = patcher.process(&mut data).unwrap_err();
+        assert_eq!(error.kind(), BitstreamErrorKind::CrcMismatch);
+    }
+
+    #[test]
+    fn position() {
+        let location = BramLocation { bank: 1, bank_offset: 16, width: 256, index: 33 };
+        assert_eq!(location.position(0), (8, 17));
+        assert_eq!(location.position(7), (15, 17));
+    }
+
+    #[test]
+    fn up5k_words() {
+        // Bit 5 of word 0x21 of the first block of the top right bank: group
+        // 2, word 1 in the group, so column 1 and row 2 * 16 + 5 of bank 3.
+        // In 160-bit rows that is bit 37 * 160 + 1 of the section.
+        let location = BramLocation { bank: 3, bank_offset: 0, width: 160, index: 740 };
+        assert_eq!(location.word(Device::Up5k, 1), Some(BramWord { tile: (19, 17), index: 0x21, bit: 5 }));
+
+        // Last block of the bottom left bank, which spans the middle row
+        let location = BramLocation { bank: 0, bank_offset: 128, width: 160, index: 2555 };
+        assert_eq!(location.position(7), (127, 255));
+        assert_eq!(location.word(Device::Up5k, 7), Some(BramWord { tile: (6, 15), index: 0xff, bit: 15 }));
+
+        // The top banks hold one block less
+        let location = BramLocation { bank: 1, bank_offset: 0, width: 160, index: 14 };
+        assert_eq!(location.position(0), (112, 0));
+        assert_eq!(location.word(Device::Up5k, 0), None);
+        assert_eq!(location.word(Device::Hx8k, 0), None);
+    }
+
+    #[test]
+    fn patch_block() {
+        // Set all bits of the block at (6, 1), the four rows of the test
+        // image hold bits 0 to 3 of its words 0 to 15
+        let mut patched = image(&[0; BRAM_BYTES], None);
+        let mut patcher = BramPatcher::for_device(Device::Up5k, |location: BramLocation, _| {
+            (0..8).fold(0, |byte, bit| match location.word(Device::Up5k, bit) {
+                Some(BramWord { tile: (6, 1), .. }) => byte | (0x80 >> bit),
+                _ => byte,
+            })
+        });
+        patcher.process(&mut patched).unwrap();
+        patcher.finish().unwrap();
+
+        let mut bram = [0; BRAM_BYTES];
+        for row in bram.chunks_mut(32) {
+            row[..2].copy_from_slice(&[0xff, 0xff]);
+        }
+        assert_eq!(patched, image(&bram, None));
+    }
+}
@@ -0,0 +1,288 @@
+use super::{parse, BitstreamError, BitstreamErrorKind};
+use crate::{trailer, AnySource, Command, Commands};
+use core::fmt;
+
+/// Number of images selectable with `SB_WARMBOOT`
+pub const MAX_IMAGES: usize = 4;
+
+const HEADER_SIZE: usize = 32;
+
+// Power-on header followed by one header per warmboot image
+const HEADERS: usize = MAX_IMAGES + 1;
+
+// Boot addresses are 24-bit
+const MAX_OFFSET: usize = 1 << 24;
+
+#[derive(Debug, PartialEq)]
+pub enum MultiImageError {
+    NoImages,
+    TooManyImages,
+    /// Power-on image index is out of range
+    InvalidPowerOnImage,
+    /// Image at this index is neither a raw nor an ICECOMPR bitstream
+    UnknownFormat(usize),
+    /// Container does not fit into the 24-bit address space
+    TooLarge,
+    BufferTooSmall,
+}
+
+impl fmt::Display for MultiImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultiImageError::NoImages => f.write_str("no images given"),
+            MultiImageError::TooManyImages => write!(f, "more than {} images given", MAX_IMAGES),
+            MultiImageError::InvalidPowerOnImage => f.write_str("power-on image index is out of range"),
+            MultiImageError::UnknownFormat(index) => write!(f, "image {} is not a bitstream", index),
+            MultiImageError::TooLarge => f.write_str("container exceeds the 24-bit address space"),
+            MultiImageError::BufferTooSmall => f.write_str("output buffer is too small"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MultiImageError {}
+
+/// Multi-image container as written by `icemulti`
+///
+/// The container starts with a header for the power-on image and one header
+/// for each image `SB_WARMBOOT` can select. Images are either raw or
+/// ICECOMPR-compressed. Only containers with raw images can be written to
+/// the configuration flash as is, compressed images have to be extracted and
+/// uploaded by the MCU.
+pub struct MultiImage<'a> {
+    data: &'a [u8],
+    /// Offsets of the power-on image and of the warmboot images
+    offsets: [usize; HEADERS],
+}
+
+impl<'a> MultiImage<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, BitstreamError> {
+        let mut offsets = [0; HEADERS];
+        for (i, offset) in offsets.iter_mut().enumerate() {
+            let start = i * HEADER_SIZE;
+            let header = data.get(start..start + HEADER_SIZE).ok_or(BitstreamError {
+                kind: BitstreamErrorKind::UnexpectedEof,
+                offset: data.len(),
+            })?;
+            let error = BitstreamError {
+                kind: BitstreamErrorKind::NotMultiImage,
+                offset: start,
+            };
+
+            // A header is a complete image that only reboots into another one
+            let info = parse(header).map_err(|_| error)?;
+            *offset = info.reboot_address.ok_or(error)? as usize;
+            if *offset < HEADERS * HEADER_SIZE || *offset >= data.len() {
+                return Err(error);
+            }
+        }
+        Ok(Self { data, offsets })
+    }
+
+    /// Whole container, e.g. to write it to the configuration flash
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Image loaded at power-on
+    pub fn power_on_image(&self) -> &'a [u8] {
+        self.image_at(self.offsets[0])
+    }
+
+    /// Image selected by `SB_WARMBOOT` inputs `S1:S0 = index`
+    ///
+    /// Raw images run up to the next image, including any padding, which the
+    /// FPGA ignores. Compressed images end with their stream and trailer, so
+    /// padding does not turn into trailing data.
+    pub fn image(&self, index: usize) -> Option<&'a [u8]> {
+        let offset = *self.offsets.get(index + 1)?;
+        Some(self.image_at(offset))
+    }
+
+    /// Source for uploading the image selected by `index`
+    pub fn source(&self, index: usize) -> Option<AnySource<'a>> {
+        AnySource::detect(self.image(index)?)
+    }
+
+    // Images end where the next one starts
+    fn image_at(&self, offset: usize) -> &'a [u8] {
+        let end = self.offsets.iter().copied().filter(|&next| next > offset).min().unwrap_or(self.data.len());
+        let image = &self.data[offset..end];
+        &image[..compressed_len(image).unwrap_or(image.len())]
+    }
+}
+
+/// Length of the ICECOMPR stream at the start of `image`, up to the end of
+/// the `Finish` command or the trailer after it
+fn compressed_len(image: &[u8]) -> Option<usize> {
+    let mut commands = Commands::new(image);
+    while !matches!(commands.next()?.ok()?, Command::Finish { .. }) {}
+    let end = commands.bit_offset().div_ceil(8);
+    if trailer::starts_with_trailer(&image[end..]) {
+        Some(end + trailer::TRAILER_LEN)
+    } else {
+        Some(end)
+    }
+}
+
+/// Writes a multi-image container into `output`
+///
+/// Images are stored back to back after the headers, `power_on` selects the
+/// image loaded at power-on. Warmboot slots without an image of their own
+/// point to the first image. Returns the size of the container.
+pub fn build_multi_image(images: &[&[u8]], power_on: usize, output: &mut [u8]) -> Result<usize, MultiImageError> {
+    if images.is_empty() {
+        return Err(MultiImageError::NoImages);
+    }
+    if images.len() > MAX_IMAGES {
+        return Err(MultiImageError::TooManyImages);
+    }
+    if power_on >= images.len() {
+        return Err(MultiImageError::InvalidPowerOnImage);
+    }
+
+    let mut offsets = [0; MAX_IMAGES];
+    let mut offset = HEADERS * HEADER_SIZE;
+    for (i, image) in images.iter().enumerate() {
+        if AnySource::detect(image).is_none() {
+            return Err(MultiImageError::UnknownFormat(i));
+        }
+        offsets[i] = offset;
+        offset += image.len();
+    }
+    if offset > MAX_OFFSET {
+        return Err(MultiImageError::TooLarge);
+    }
+    if offset > output.len() {
+        return Err(MultiImageError::BufferTooSmall);
+    }
+
+    write_header(&mut output[..HEADER_SIZE], offsets[power_on]);
+    for slot in 0..MAX_IMAGES {
+        let image = if slot < images.len() { slot } else { 0 };
+        let start = (slot + 1) * HEADER_SIZE;
+        write_header(&mut output[start..start + HEADER_SIZE], offsets[image]);
+    }
+    for (image, &start) in images.iter().zip(&offsets) {
+        output[start..start + image.len()].copy_from_slice(image);
+    }
+    Ok(offset)
+}
+
+fn write_header(header: &mut [u8], offset: usize) {
+    let address = (offset as u32).to_be_bytes();
+    let commands = [
+        0x7e, 0xaa, 0x99, 0x7e, // Preamble
+        0x92, 0x00, 0x00, // Boot mode
+        0x44, 0x03, address[1], address[2], address[3], // Boot address, after the SPI read opcode
+        0x82, 0x00, 0x00, // Bank offset
+        0x01, 0x08, // Reboot
+    ];
+    header[..commands.len()].copy_from_slice(&commands);
+    for byte in &mut header[commands.len()..] {
+        *byte = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::super::tests::{image, BRAM_BYTES};
+    use super::*;
+    use crate::tests::compress;
+    use crate::BitstreamSource;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    fn build_and_parse() {
+        let first = image(&[0x11; BRAM_BYTES], None);
+        let second = image(&[0x22; BRAM_BYTES], None);
+        let mut container = vec![0; 2048];
+        let len = build_multi_image(&[&first, &second], 1, &mut container).unwrap();
+        container.truncate(len);
+
+        let first_offset = HEADERS * HEADER_SIZE;
+        let second_offset = first_offset + first.len();
+        assert_eq!(len, second_offset + second.len());
+
+        // Power-on image, then the warmboot slots, unused ones boot the first image
+        let expected = [second_offset, first_offset, second_offset, first_offset, first_offset];
+        for (i, &offset) in expected.iter().enumerate() {
+            let header = &container[i * HEADER_SIZE..(i + 1) * HEADER_SIZE];
+            let info = parse(header).unwrap();
+            assert_eq!(info.reboot_address, Some(offset as u32), "header {}", i);
+        }
+
+        let multi = MultiImage::parse(&container).unwrap();
+        assert_eq!(multi.power_on_image(), &second[..]);
+        assert_eq!(multi.image(0), Some(&first[..]));
+        assert_eq!(multi.image(1), Some(&second[..]));
+        assert_eq!(multi.image(2), Some(&first[..]));
+        assert_eq!(multi.image(MAX_IMAGES), None);
+        for index in 0..2 {
+            assert!(parse(multi.image(index).unwrap()).is_ok());
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let image = image(&[0; BRAM_BYTES], None);
+        let mut container = [0; 2048];
+        assert_eq!(build_multi_image(&[], 0, &mut container), Err(MultiImageError::NoImages));
+        assert_eq!(build_multi_image(&[&image[..]; 5], 0, &mut container), Err(MultiImageError::TooManyImages));
+        assert_eq!(build_multi_image(&[&image], 1, &mut container), Err(MultiImageError::InvalidPowerOnImage));
+        assert_eq!(build_multi_image(&[&image, &[1, 2, 3]], 0, &mut container), Err(MultiImageError::UnknownFormat(1)));
+        assert_eq!(build_multi_image(&[&image], 0, &mut container[..200]), Err(MultiImageError::BufferTooSmall));
+    }
+
+    fn read_all(source: &mut AnySource) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut buf = [0; 64];
+        loop {
+            match source.read(&mut buf).unwrap() {
+                0 => return output,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_images() {
+        let first = image(&[0x11; BRAM_BYTES], None);
+        let second = image(&[0x22; BRAM_BYTES], None);
+        let compressed = compress(&first, true);
+
+        // Back to back as written by `build_multi_image`
+        let mut container = vec![0; 2048];
+        let len = build_multi_image(&[&compressed, &second], 0, &mut container).unwrap();
+        let multi = MultiImage::parse(&container[..len]).unwrap();
+        assert_eq!(multi.image(0), Some(&compressed[..]));
+        assert_eq!(read_all(&mut multi.source(0).unwrap()), first);
+        assert_eq!(read_all(&mut multi.source(1).unwrap()), second);
+
+        // Images aligned to 256 bytes, padded with zeros
+        let second_offset = (256 + compressed.len()).next_multiple_of(256);
+        let mut container = vec![0; 256];
+        let offsets = [256, 256, second_offset, 256, 256];
+        for (i, &offset) in offsets.iter().enumerate() {
+            write_header(&mut container[i * HEADER_SIZE..(i + 1) * HEADER_SIZE], offset);
+        }
+        container.extend_from_slice(&compressed);
+        container.resize(second_offset, 0);
+        container.extend_from_slice(&second);
+        let multi = MultiImage::parse(&container).unwrap();
+        assert_eq!(multi.image(0), Some(&compressed[..]));
+        assert_eq!(read_all(&mut multi.source(0).unwrap()), first);
+        assert_eq!(multi.image(1), Some(&second[..]));
+
+        // Without the trailer the stream ends after the `Finish` command
+        let compressed = compress(&first, false);
+        container[256..second_offset].fill(0);
+        container[256..256 + compressed.len()].copy_from_slice(&compressed);
+        let multi = MultiImage::parse(&container).unwrap();
+        assert_eq!(multi.image(0), Some(&compressed[..]));
+        assert_eq!(read_all(&mut multi.source(0).unwrap()), first);
+    }
+}
@@ -0,0 +1,62 @@
+use std::io;
+use crate::{Decoder, DecoderError, DecoderErrorKind, EncoderError};
+
+impl std::error::Error for DecoderError {}
+
+impl std::error::Error for EncoderError {}
+
+impl From<DecoderError> for io::Error {
+    fn from(e: DecoderError) -> Self {
+        let kind = match e.kind() {
+            DecoderErrorKind::InvalidHeader => io::ErrorKind::InvalidData,
+            DecoderErrorKind::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+            DecoderErrorKind::InvalidState => io::ErrorKind::Other,
+            DecoderErrorKind::TrailingData => io::ErrorKind::InvalidData,
+            DecoderErrorKind::LengthMismatch => io::ErrorKind::InvalidData,
+            DecoderErrorKind::ChecksumMismatch => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+// Use `std::io::BufReader` on top of the decoder to get `BufRead`
+impl<const N: usize> io::Read for Decoder<'_, N> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Decoder::read(self, buf).map_err(io::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{compress, sample_data};
+    use std::io::Read;
+
+    #[test]
+    fn read_to_end() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+
+        let mut output = Vec::new();
+        Decoder::new(&compressed).read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+
+        let mut output = Vec::new();
+        let copied = io::copy(&mut Decoder::<4>::with_capacity(&compressed), &mut output).unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn error_kinds() {
+        let compressed = compress(&sample_data(), false);
+
+        let error = Decoder::new(&compressed[..20]).read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let inner = error.get_ref().unwrap().downcast_ref::<DecoderError>().unwrap();
+        assert_eq!(inner.kind(), DecoderErrorKind::UnexpectedEof);
+
+        let error = Decoder::new(b"ICECOMPX\x80").read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
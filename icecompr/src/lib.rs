@@ -1,43 +1,55 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use heapless::spsc::Queue;
-use heapless::consts::U16;
+mod encoder;
+pub use encoder::{compress, Encoder, EncoderError};
 
-struct RingBuffer {
-    queue: Queue<u8, U16>,
-}
+mod error;
+pub use error::{DecoderError, DecoderErrorKind};
 
-impl RingBuffer {
-    pub fn new() -> Self {
-        Self {
-            queue: Queue::new()
-        }
-    }
+mod stream;
+pub use stream::StreamDecoder;
 
-    pub fn space_available(&self) -> usize {
-        self.queue.capacity() - self.queue.len()
-    }
+mod sink;
+pub use sink::SinkError;
 
-    pub fn read(&mut self, buf: &mut [u8]) -> usize {
-        let mut n = 0;
-        for byte in buf.iter_mut() {
-            if let Some(b) = self.queue.dequeue() {
-                *byte = b;
-                n += 1;
-            }
-        }
-        n
-    }
+mod async_sink;
+pub use async_sink::AsyncSink;
 
-    pub fn write_byte(&mut self, byte: u8) -> Result<(), ()> {
-        self.queue.enqueue(byte).map_err(|_| ())
-    }
-}
+mod command;
+pub use command::{Command, Commands};
+
+mod scan;
+pub use scan::{scan, ScanInfo};
+
+mod checkpoint;
+pub use checkpoint::Checkpoint;
+
+mod source;
+pub use source::{AnySource, BitstreamSource, RawSource};
+
+pub mod ice40;
+
+mod crc32;
+pub use crc32::Crc32;
+
+mod trailer;
+use trailer::{Checksum, ChecksumOutput, Trailer};
+
+#[cfg(feature = "std")]
+pub mod build;
+
+#[cfg(feature = "std")]
+mod io;
+
+/// Default size of the decoder output buffer in bytes
+pub const DEFAULT_BUFFER_SIZE: usize = 16;
 
 pub struct DecoderReader<'a> {
     bytes: &'a [u8],
-    bits: u32,
+    bits: u64,
     bit_count: u8,
+    /// Number of bits moved from `bytes` into `bits` so far
+    fed_bits: usize,
 }
 
 impl<'a> DecoderReader<'a> {
@@ -46,6 +58,7 @@ impl<'a> DecoderReader<'a> {
             bytes,
             bits: 0,
             bit_count: 0,
+            fed_bits: 0,
         }
     }
 
@@ -61,6 +74,11 @@ impl<'a> DecoderReader<'a> {
         (self.bytes.len() * 8) + (self.bit_count as usize)
     }
 
+    /// Number of bits consumed since the start of the data
+    pub fn bit_offset(&self) -> usize {
+        self.fed_bits - (self.bit_count as usize)
+    }
+
     pub fn read_bit(&mut self) -> Option<bool> {
         self.feed_bits();
         if self.bit_count > 0 {
@@ -70,26 +88,84 @@ impl<'a> DecoderReader<'a> {
         }
     }
 
-    pub fn read_int(&mut self, mut bits: usize) -> Option<u32> {
+    pub fn read_int(&mut self, bits: usize) -> Option<u32> {
+        let value = self.peek_int(bits)?;
+        self.bit_count -= bits as u8;
+        Some(value)
+    }
+
+    /// Returns the next `bits` bits (up to 32) without consuming them
+    pub fn peek_int(&mut self, bits: usize) -> Option<u32> {
         self.feed_bits();
         if (self.bit_count as usize) >= bits {
-            let mut value = 0;
-            while bits > 0 {
-                bits -= 1;
-                if self.read_bit_internal() {
-                    value |= 1 << bits;
-                }
-            }
-            Some(value)
+            let value = self.bits >> (self.bit_count as usize - bits);
+            Some((value & ((1 << bits) - 1)) as u32)
         } else {
-            return None
+            None
+        }
+    }
+
+    /// Decodes a command only if all of its bits are available
+    pub(crate) fn read_command(&mut self) -> Option<DecoderState> {
+        let (prefix, _, value) = self.read_command_code()?;
+        let state = match prefix {
+            3 => DecoderState::WriteDataOne(value),
+            5 => DecoderState::WriteZeroFinish(value),
+            _ => DecoderState::WriteZeroOne(value),
+        };
+        Some(state)
+    }
+
+    /// Reads the next command code only if all of its bits are available
+    ///
+    /// Returns the number of zeros in the prefix, the width of the value
+    /// field and the value.
+    pub(crate) fn read_command_code(&mut self) -> Option<(usize, usize, u32)> {
+        let mut prefix = 0;
+        while prefix < 5 && (self.peek_int(prefix + 1)? & 1) == 0 {
+            prefix += 1;
+        }
+
+        let (prefix_bits, value_bits) = match prefix {
+            0 => (1, 2),
+            1 => (2, 5),
+            2 => (3, 8),
+            3 => (4, 6),
+            4 => (5, 23),
+            _ => (5, 23),
+        };
+        let value = self.peek_int(prefix_bits + value_bits)? & ((1 << value_bits) - 1);
+        self.read_int(prefix_bits + value_bits)?;
+        Some((prefix, value_bits, value))
+    }
+
+    /// Checks the "ICECOMPR" magic, the caller makes sure it is buffered
+    ///
+    /// Stops in front of the first byte that does not match.
+    pub(crate) fn read_header(&mut self) -> Result<(), DecoderErrorKind> {
+        for &byte in b"ICECOMPR" {
+            if self.peek_int(8) != Some(byte as u32) {
+                return Err(DecoderErrorKind::InvalidHeader);
+            }
+            self.bit_count -= 8;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn skip_bits(&mut self, mut bits: usize) -> Option<()> {
+        while bits > 0 {
+            let n = core::cmp::min(bits, 32);
+            self.read_int(n)?;
+            bits -= n;
         }
+        Some(())
     }
 
     fn feed_bits(&mut self) {
-        while self.bit_count <= 24 && !self.bytes.is_empty() {
-            self.bits = (self.bits << 8) | (self.bytes[0] as u32);
+        while self.bit_count <= 56 && !self.bytes.is_empty() {
+            self.bits = (self.bits << 8) | (self.bytes[0] as u64);
             self.bit_count += 8;
+            self.fed_bits += 8;
             self.bytes = &self.bytes[1..];
         }
     }
@@ -100,243 +176,749 @@ impl<'a> DecoderReader<'a> {
     }
 }
 
-pub struct DecoderWriter {
-    buffer: RingBuffer,
-    bits: u32,
-    bit_count: u8,
+/// Destination of decoded bytes
+trait Output {
+    /// Number of bytes that can still be written
+    fn space(&self) -> usize;
+
+    fn write_byte(&mut self, byte: u8);
+
+    fn write_zero_bytes(&mut self, count: usize);
+}
+
+struct SliceOutput<'b> {
+    buf: &'b mut [u8],
+    offset: usize,
+}
+
+impl Output for SliceOutput<'_> {
+    fn space(&self) -> usize {
+        self.buf.len() - self.offset
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.buf[self.offset] = byte;
+        self.offset += 1;
+    }
+
+    fn write_zero_bytes(&mut self, count: usize) {
+        for byte in &mut self.buf[self.offset..self.offset + count] {
+            *byte = 0;
+        }
+        self.offset += count;
+    }
+}
+
+/// Output that only counts the bytes it is given
+struct SkipOutput {
+    remaining: usize,
+}
+
+impl Output for SkipOutput {
+    fn space(&self) -> usize {
+        self.remaining
+    }
+
+    fn write_byte(&mut self, _byte: u8) {
+        self.remaining -= 1;
+    }
+
+    fn write_zero_bytes(&mut self, count: usize) {
+        self.remaining -= count;
+    }
+}
+
+/// Output byte that is not complete yet, filled MSB first
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct PartialByte {
+    bits: u8,
+    count: u8,
+}
+
+impl PartialByte {
+    fn emit(&mut self, out: &mut impl Output) {
+        out.write_byte(self.bits);
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    /// Writes up to `count` zero bits, filling whole output bytes at once
+    fn write_zeros(&mut self, out: &mut impl Output, count: u32) -> u32 {
+        if out.space() == 0 {
+            return 0;
+        }
+
+        let mut written = 0;
+        if self.count > 0 {
+            let n = core::cmp::min(count, 8 - self.count as u32);
+            self.count += n as u8;
+            written += n;
+            if self.count == 8 {
+                self.emit(out);
+            }
+        }
+
+        if self.count == 0 {
+            let bytes = core::cmp::min(((count - written) / 8) as usize, out.space());
+            out.write_zero_bytes(bytes);
+            written += bytes as u32 * 8;
+
+            if count - written < 8 {
+                self.count = (count - written) as u8;
+                written = count;
+            }
+        }
+        written
+    }
+
+    fn write_one(&mut self, out: &mut impl Output) -> bool {
+        if out.space() == 0 {
+            return false;
+        }
+
+        self.bits |= 0x80 >> self.count;
+        self.count += 1;
+        if self.count == 8 {
+            self.emit(out);
+        }
+        true
+    }
+
+    /// Copies up to `count` bits from the reader, up to a byte at a time
+    fn write_data(&mut self, out: &mut impl Output, reader: &mut DecoderReader, count: u32) -> u32 {
+        let mut written = 0;
+        while written < count && out.space() > 0 {
+            let available = reader.len_bits();
+            if available == 0 {
+                break;
+            }
+
+            let n = core::cmp::min(core::cmp::min(count - written, 8 - self.count as u32), available as u32);
+            let value = match reader.read_int(n as usize) {
+                Some(value) => value,
+                None => break,
+            };
+            self.bits |= (value << (8 - self.count as u32 - n)) as u8;
+            self.count += n as u8;
+            written += n;
+            if self.count == 8 {
+                self.emit(out);
+            }
+        }
+        written
+    }
+}
+
+/// Decoded data that did not fit into the caller's buffer yet
+pub struct DecoderWriter<const N: usize = DEFAULT_BUFFER_SIZE> {
+    buffer: [u8; N],
+    start: usize,
+    end: usize,
+    partial: PartialByte,
 }
 
 impl DecoderWriter {
     pub fn new() -> Self {
+        Self::with_capacity()
+    }
+}
+
+impl<const N: usize> DecoderWriter<N> {
+    // Evaluated at compile time for each buffer size in use
+    const NON_EMPTY: () = assert!(N > 0, "buffer size must be non-zero");
+
+    /// Creates a writer with an `N`-byte output buffer
+    pub fn with_capacity() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::NON_EMPTY;
+
         Self {
-            buffer: RingBuffer::new(),
-            bits: 0,
-            bit_count: 0,
+            buffer: [0; N],
+            start: 0,
+            end: 0,
+            partial: PartialByte::default(),
         }
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> usize {
-        let n = self.buffer.read(buf);
-        if n < buf.len() {
-            self.flush();
-            let n2 = self.buffer.read(&mut buf[n..]);
-            n + n2
-        } else {
-            n
+        let n = core::cmp::min(buf.len(), self.end - self.start);
+        buf[..n].copy_from_slice(&self.buffer[self.start..self.start + n]);
+        self.start += n;
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
         }
+        n
     }
 
-    pub fn space_bits(&self) -> usize {
-        (32 - self.bit_count as usize) + (self.buffer.space_available() * 8)
+    /// Drops up to `count` buffered bytes
+    fn skip(&mut self, count: usize) -> usize {
+        let n = core::cmp::min(count, self.end - self.start);
+        self.start += n;
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
+        }
+        n
     }
 
-    pub fn write_zeros(&mut self, mut count: u32) -> u32 {
-        self.flush();
+    pub fn space_bits(&self) -> usize {
+        ((N - self.end) * 8).saturating_sub(self.partial.count as usize)
+    }
 
-        let mut written = 0;
-        while count > 0 && self.bit_count < 32 {
-            let n = core::cmp::min(count, 32 - self.bit_count as u32);
-            self.bit_count += n as u8;
-            written += n;
-            count -= n;
-            self.flush();
-        }
+    pub fn write_zeros(&mut self, count: u32) -> u32 {
+        let mut out = SliceOutput { buf: &mut self.buffer, offset: self.end };
+        let written = self.partial.write_zeros(&mut out, count);
+        self.end = out.offset;
         written
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn write_bit(&mut self, bit: bool) -> Result<(), ()> {
-        self.flush();
-        if self.bit_count < 32 {
-            self.bits |= (bit as u32) << self.bit_count;
-            self.bit_count += 1;
+        let written = if bit {
+            let mut out = SliceOutput { buf: &mut self.buffer, offset: self.end };
+            let written = self.partial.write_one(&mut out);
+            self.end = out.offset;
+            written
+        } else {
+            self.write_zeros(1) == 1
+        };
+        if written {
             Ok(())
         } else {
             Err(())
         }
     }
 
+    /// Drops the incomplete last byte of the output
     pub fn write_trailing_zeros(&mut self) {
-        let n = self.bit_count & 7;
-        if n > 0 {
-            self.bit_count += 7 - n;
-        }
+        self.partial = PartialByte::default();
     }
+}
 
-    fn flush(&mut self) {
-        while self.bit_count >= 8 {
-            if self.buffer.write_byte((self.bits as u8).reverse_bits()).is_err() {
-                return;
-            }
-            self.bit_count -= 8;
-            self.bits = self.bits >> 8;
-        }
+impl<const N: usize> Default for DecoderWriter<N> {
+    fn default() -> Self {
+        Self::with_capacity()
     }
 }
 
-pub struct Decoder<'a> {
+pub struct Decoder<'a, const N: usize = DEFAULT_BUFFER_SIZE> {
+    compressed: &'a [u8],
     reader: DecoderReader<'a>,
-    writer: DecoderWriter,
-    state: DecoderState,
+    core: DecoderCore<N>,
 }
 
 impl<'a> Decoder<'a> {
     pub fn new(compressed: &'a [u8]) -> Self {
+        Self::with_capacity(compressed)
+    }
+}
+
+impl<'a, const N: usize> Decoder<'a, N> {
+    /// Creates a decoder with an `N`-byte output buffer
+    ///
+    /// A larger buffer lets the decoder run longer between `read` calls
+    /// at the cost of RAM, e.g. `Decoder::<256>::with_capacity(compressed)`.
+    pub fn with_capacity(compressed: &'a [u8]) -> Self {
         Self {
+            compressed,
             reader: DecoderReader::new(compressed),
-            writer: DecoderWriter::new(),
-            state: DecoderState::Initial,
+            // Checksums are only computed if there is something to check
+            core: DecoderCore::new(trailer::has_trailer(compressed)),
         }
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, DecoderError> {
-        let mut offset = 0;
-        while offset < buf.len() {
-            let n = self.writer.read(&mut buf[offset..]);
-            if n > 0 {
-                offset += n;
-            }
+        self.core.read(&mut self.reader, true, buf)
+    }
+
+    /// Skips `count` bytes of decompressed data without producing them
+    ///
+    /// Zero runs are skipped arithmetically, so this is much cheaper than
+    /// reading. Returns the number of bytes skipped, which is less than
+    /// `count` only at the end of the stream.
+    pub fn skip(&mut self, count: usize) -> Result<usize, DecoderError> {
+        self.core.skip(&mut self.reader, true, count)
+    }
+}
+
+/// Decoder state machine shared by the slice and the streaming decoders
+struct DecoderCore<const N: usize> {
+    writer: DecoderWriter<N>,
+    state: DecoderState,
+    /// Number of commands decoded completely
+    commands: usize,
+    /// Number of bytes returned to the caller or skipped
+    output_offset: usize,
+    /// Checksum of the data decoded so far, to be compared with the trailer
+    checksum: Option<Checksum>,
+}
 
+impl<const N: usize> DecoderCore<N> {
+    fn new(checksum: bool) -> Self {
+        Self {
+            writer: DecoderWriter::with_capacity(),
+            state: DecoderState::Initial,
+            commands: 0,
+            output_offset: 0,
+            checksum: if checksum { Some(Checksum::new()) } else { None },
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.state == DecoderState::Finished
+    }
+
+    /// Attaches the current position to an error
+    ///
+    /// `pending` is the number of bytes produced by the current call.
+    fn error(&self, kind: DecoderErrorKind, reader: &DecoderReader, pending: usize) -> DecoderError {
+        let buffered = self.writer.end - self.writer.start;
+        DecoderError::new(kind, reader.bit_offset(), self.commands, self.output_offset + pending + buffered)
+    }
+
+    /// Decodes data into `buf` until it is full, the stream is finished
+    /// or (if `complete` is false) the reader runs out of input bits.
+    fn read(&mut self, reader: &mut DecoderReader, complete: bool, buf: &mut [u8]) -> Result<usize, DecoderError> {
+        let n = self.read_inner(reader, complete, buf)?;
+        self.output_offset += n;
+        Ok(n)
+    }
+
+    fn read_inner(&mut self, reader: &mut DecoderReader, complete: bool, buf: &mut [u8]) -> Result<usize, DecoderError> {
+        let mut offset = self.writer.read(buf);
+        while offset < buf.len() {
             if self.state == DecoderState::Finished {
+                if offset == 0 && reader.len_bits() >= 8 {
+                    return Err(self.error(DecoderErrorKind::TrailingData, reader, offset));
+                }
                 break; // Return data read or EOF
             }
             if self.state == DecoderState::Error {
-                return Err(DecoderError::InvalidState);
+                return Err(self.error(DecoderErrorKind::InvalidState, reader, offset));
             }
 
-            // No more data to read, decode something
-            self.decode_one()?;
+            let result = if buf.len() - offset >= N {
+                // Large reads are decoded straight into the caller's buffer
+                let mut out = SliceOutput { buf: &mut *buf, offset };
+                let result = Self::decode(&mut self.state, &mut self.writer.partial, &mut self.commands, &mut self.checksum, reader, complete, &mut out);
+                offset = out.offset;
+                result
+            } else {
+                let writer = &mut self.writer;
+                let mut out = SliceOutput { buf: &mut writer.buffer, offset: writer.end };
+                let result = Self::decode(&mut self.state, &mut writer.partial, &mut self.commands, &mut self.checksum, reader, complete, &mut out);
+                writer.end = out.offset;
+                offset += writer.read(&mut buf[offset..]);
+                result
+            };
+            match result {
+                Ok(true) => {}
+                Ok(false) => break, // Waiting for more input
+                Err(kind) => return Err(self.error(kind, reader, offset)),
+            }
         }
         Ok(offset)
     }
 
-    fn read_bit(&mut self) -> Result<bool, DecoderError> {
-        self.reader.read_bit().ok_or(DecoderError::UnexpectedEof)
-    }
-
-    fn read_int(&mut self, bits: usize) -> Result<u32, DecoderError> {
-        self.reader.read_int(bits).ok_or(DecoderError::UnexpectedEof)
+    fn skip(&mut self, reader: &mut DecoderReader, complete: bool, count: usize) -> Result<usize, DecoderError> {
+        let n = self.skip_inner(reader, complete, count)?;
+        self.output_offset += n;
+        Ok(n)
     }
 
-    fn decode_one(&mut self) -> Result<(), DecoderError> {
-        match self.state {
-            DecoderState::Initial => {
-                let compressed = self.reader.inner();
-
-                if compressed.len() < 8 {
-                    self.state = DecoderState::Error;
-                    return Err(DecoderError::InvalidHeader);
-                }
-
-                if &compressed[..8] != b"ICECOMPR" {
-                    self.state = DecoderState::Error;
-                    return Err(DecoderError::InvalidHeader);
+    fn skip_inner(&mut self, reader: &mut DecoderReader, complete: bool, count: usize) -> Result<usize, DecoderError> {
+        let mut skipped = self.writer.skip(count);
+        while skipped < count {
+            if self.state == DecoderState::Finished {
+                if skipped == 0 && reader.len_bits() >= 8 {
+                    return Err(self.error(DecoderErrorKind::TrailingData, reader, skipped));
                 }
+                break;
+            }
+            if self.state == DecoderState::Error {
+                return Err(self.error(DecoderErrorKind::InvalidState, reader, skipped));
+            }
 
-                self.reader.bytes = &self.reader.bytes[8..];
+            let mut out = SkipOutput { remaining: count - skipped };
+            let result = Self::decode(&mut self.state, &mut self.writer.partial, &mut self.commands, &mut self.checksum, reader, complete, &mut out);
+            skipped = count - out.remaining;
+            match result {
+                Ok(true) => {}
+                Ok(false) => break, // Waiting for more input
+                Err(kind) => return Err(self.error(kind, reader, skipped)),
+            }
+        }
+        Ok(skipped)
+    }
 
-                self.state = DecoderState::Started;
-                Ok(())
+    /// Decodes commands into `out` until it is full
+    ///
+    /// Returns `Ok(false)` if more input is needed to make progress.
+    fn decode(
+        state: &mut DecoderState,
+        partial: &mut PartialByte,
+        commands: &mut usize,
+        checksum: &mut Option<Checksum>,
+        reader: &mut DecoderReader,
+        complete: bool,
+        out: &mut impl Output,
+    ) -> Result<bool, DecoderErrorKind> {
+        let mut result = match checksum {
+            Some(checksum) => Self::decode_inner(state, partial, commands, reader, complete, &mut ChecksumOutput { out, checksum }),
+            None => Self::decode_inner(state, partial, commands, reader, complete, out),
+        };
+        if result == Ok(true) && *state == DecoderState::Trailer {
+            result = Self::check_trailer(checksum.as_ref(), reader, complete);
+            if result == Ok(true) {
+                *state = DecoderState::Finished;
             }
-            DecoderState::Started => {
-                match self.decode_command() {
-                    Ok(state) => {
-                        self.state = state;
+        }
+        if result.is_err() {
+            *state = DecoderState::Error;
+        }
+        result
+    }
 
-                        // Call the function again to fill the output buffer
-                        self.decode_one()
-                    }
-                    Err(e) => {
-                        self.state = DecoderState::Error;
-                        Err(e)
-                    }
+    /// Compares the trailer, if there is one, with the decoded data
+    fn check_trailer(checksum: Option<&Checksum>, reader: &mut DecoderReader, complete: bool) -> Result<bool, DecoderErrorKind> {
+        match trailer::read_trailer(reader, complete)? {
+            Trailer::Pending => Ok(false),
+            Trailer::Absent => Ok(true),
+            Trailer::Present { len, crc } => {
+                // Without a checksum the trailer is skipped like `Commands` does.
+                // `Decoder` only goes without one if the trailer is not at the
+                // end of the data, which is reported as trailing data, and
+                // `StreamDecoder` if created with `without_trailer_check`.
+                if let Some(checksum) = checksum {
+                    checksum.verify(len, crc)?;
                 }
+                Ok(true)
             }
-            DecoderState::WriteZeroOne(count) => {
-                if count > 0 {
-                    let n = self.writer.write_zeros(count);
-                    if n > 0 {
-                        self.state = DecoderState::WriteZeroOne(count - n);
-                    }
-                } else {
-                    if self.writer.write_bit(true).is_ok() {
-                        self.state = DecoderState::Started;
+        }
+    }
+
+    fn decode_inner(
+        state: &mut DecoderState,
+        partial: &mut PartialByte,
+        commands: &mut usize,
+        reader: &mut DecoderReader,
+        complete: bool,
+        out: &mut impl Output,
+    ) -> Result<bool, DecoderErrorKind> {
+        while out.space() > 0 {
+            match *state {
+                DecoderState::Initial => {
+                    if reader.len_bits() < 64 {
+                        if !complete {
+                            return Ok(false);
+                        }
+                        return Err(DecoderErrorKind::InvalidHeader);
                     }
+
+                    reader.read_header()?;
+                    *state = DecoderState::Started;
                 }
-                Ok(())
-            }
-            DecoderState::WriteZeroFinish(count) => {
-                if count > 0 {
-                    let n = self.writer.write_zeros(count);
-                    if n > 0 {
-                        self.state = DecoderState::WriteZeroFinish(count - n);
+                DecoderState::Started => {
+                    match reader.read_command() {
+                        Some(next) => *state = next,
+                        None if !complete => return Ok(false),
+                        None => return Err(DecoderErrorKind::UnexpectedEof),
                     }
-                } else {
-                    self.writer.write_trailing_zeros();
-                    self.state = DecoderState::Finished;
                 }
-                Ok(())
-            }
-            DecoderState::WriteDataOne(count) => {
-                if self.reader.len_bits() < (count as usize) {
-                    self.state = DecoderState::Error;
-                    return Err(DecoderError::UnexpectedEof);
+                DecoderState::WriteZeroOne(count) => {
+                    let count = count - partial.write_zeros(out, count);
+                    *state = DecoderState::WriteZeroOne(count);
+                    if count == 0 && partial.write_one(out) {
+                        *state = DecoderState::Started;
+                        *commands += 1;
+                    }
                 }
-                if count > 0 {
-                    let mut written = 0;
-                    while written < count && self.writer.space_bits() > 0 {
-                        if let Some(bit) = self.reader.read_bit() {
-                            assert!(self.writer.write_bit(bit).is_ok());
-                            written += 1;
-                        } else {
-                            break;
-                        }
+                DecoderState::WriteZeroFinish(count) => {
+                    let count = count - partial.write_zeros(out, count);
+                    *state = DecoderState::WriteZeroFinish(count);
+                    if count == 0 {
+                        // Incomplete last byte is dropped
+                        *partial = PartialByte::default();
+                        *state = DecoderState::Trailer;
+                        *commands += 1;
+                        break;
                     }
-                    if written > 0 {
-                        self.state = DecoderState::WriteDataOne(count - written);
+                }
+                DecoderState::WriteDataOne(count) => {
+                    if complete && reader.len_bits() < (count as usize) {
+                        return Err(DecoderErrorKind::UnexpectedEof);
                     }
-                } else {
-                    if self.writer.write_bit(true).is_ok() {
-                        self.state = DecoderState::Started;
+                    let written = partial.write_data(out, reader, count);
+                    let count = count - written;
+                    *state = DecoderState::WriteDataOne(count);
+                    if count == 0 {
+                        if partial.write_one(out) {
+                            *state = DecoderState::Started;
+                            *commands += 1;
+                        }
+                    } else if written == 0 && reader.is_empty() {
+                        return Ok(false);
                     }
                 }
-                Ok(())
+                DecoderState::Trailer => break, // Checked by `decode`
+                DecoderState::Finished | DecoderState::Error => {
+                    return Err(DecoderErrorKind::InvalidState);
+                }
             }
-            _ => unimplemented!()
         }
+        Ok(true)
     }
-
-    fn decode_command(&mut self) -> Result<DecoderState, DecoderError> {
-        let state = if self.read_bit()? {
-            DecoderState::WriteZeroOne(self.read_int(2)?)
-        } else if self.read_bit()? {
-            DecoderState::WriteZeroOne(self.read_int(5)?)
-        } else if self.read_bit()? {
-            DecoderState::WriteZeroOne(self.read_int(8)?)
-        } else if self.read_bit()? {
-            DecoderState::WriteDataOne(self.read_int(6)?)
-        } else if self.read_bit()? {
-            DecoderState::WriteZeroOne(self.read_int(23)?)
-        } else {
-            DecoderState::WriteZeroFinish(self.read_int(23)?)
-        };
-        Ok(state)
-    }
-}
-
-#[derive(Debug, PartialEq)]
-pub enum DecoderError {
-    InvalidHeader,
-    UnexpectedEof,
-    InvalidState
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum DecoderState {
     Initial,
     Started,
     WriteZeroOne(u32),
     WriteZeroFinish(u32),
     WriteDataOne(u32),
+    /// After the `Finish` command, before the optional trailer
+    Trailer,
     Finished,
     Error,
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Data resembling a bitstream: long zero runs, sparse set bits and
+    /// densely populated regions
+    pub(crate) fn sample_data() -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut data = Vec::new();
+        for _ in 0..40 {
+            data.resize(data.len() + (next() % 300) as usize, 0);
+            for _ in 0..next() % 40 {
+                data.push(next() as u8);
+            }
+            data.push(1 << (next() % 8));
+        }
+        data
+    }
+
+    /// Compresses `data`, with an integrity trailer if `trailer` is set
+    pub(crate) fn compress(data: &[u8], trailer: bool) -> Vec<u8> {
+        let mut encoder = if trailer { Encoder::with_trailer(data) } else { Encoder::new(data) };
+        let mut compressed = vec![0; data.len() * 2 + 64];
+        let n = encoder.read(&mut compressed).unwrap();
+        assert!(encoder.is_finished());
+        compressed.truncate(n);
+        compressed
+    }
+
+    /// Stream with the header followed by the commands in `bits`, a string
+    /// of '0' and '1' in which other characters are ignored
+    pub(crate) fn stream(bits: &str) -> Vec<u8> {
+        let mut stream = b"ICECOMPR".to_vec();
+        let bits: Vec<bool> = bits.chars().filter(|c| *c == '0' || *c == '1').map(|c| c == '1').collect();
+        for byte in bits.chunks(8) {
+            stream.push(byte.iter().enumerate().map(|(i, &bit)| (bit as u8) << (7 - i)).sum());
+        }
+        stream
+    }
+
+    /// Reads the rest of the stream with reads of up to `chunk` bytes
+    pub(crate) fn read_to_end<const N: usize>(decoder: &mut Decoder<N>, chunk: usize) -> Result<Vec<u8>, DecoderError> {
+        let mut output = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+            match decoder.read(&mut buf)? {
+                0 => return Ok(output),
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Feeds `compressed` to a `StreamDecoder` one byte at a time
+    fn stream_decode_bytewise(compressed: &[u8]) -> Result<usize, DecoderError> {
+        let mut buf = [0; 64];
+        let mut decoder = StreamDecoder::new();
+        let mut total = 0;
+        for &byte in compressed {
+            assert_eq!(decoder.feed(&[byte]), 1);
+            loop {
+                match decoder.read(&mut buf)? {
+                    0 => break,
+                    n => total += n,
+                }
+            }
+        }
+        decoder.finish();
+        loop {
+            match decoder.read(&mut buf)? {
+                0 => return Ok(total),
+                n => total += n,
+            }
+        }
+    }
+
+    /// Decodes `compressed` with `Decoder`, `StreamDecoder` and `scan`, checks
+    /// that they agree and returns the decompressed length
+    fn decode_all_ways(compressed: &[u8]) -> Result<usize, DecoderError> {
+        let mut buf = [0; 64];
+        let mut decoder = Decoder::new(compressed);
+        let mut total = 0;
+        let result = loop {
+            match decoder.read(&mut buf) {
+                Ok(0) => break Ok(total),
+                Ok(n) => total += n,
+                Err(error) => break Err(error),
+            }
+        };
+
+        assert_eq!(crate::scan(compressed).map(|info| info.decompressed_len), result);
+
+        let stream_result = stream_decode_bytewise(compressed);
+        assert_eq!(stream_result.map_err(|error| error.kind()), result.map_err(|error| error.kind()));
+        result
+    }
+
+    /// Output offsets that fall inside a zero run, inside a raw run, and
+    /// right after an output byte that two commands share
+    fn skip_offsets(compressed: &[u8]) -> Vec<usize> {
+        let (mut zero_runs, mut raw_runs, mut partial_bytes) = (Vec::new(), Vec::new(), Vec::new());
+        let mut bit = 0;
+        for command in Commands::new(compressed) {
+            let command = command.unwrap();
+            let middle = (bit + command.output_bits() as usize / 2) / 8;
+            match command {
+                Command::ZeroRun { len, .. } if len >= 64 => zero_runs.push(middle),
+                Command::Raw { len, .. } if len >= 32 => raw_runs.push(middle),
+                _ if bit % 8 != 0 => partial_bytes.push(bit / 8 + 1),
+                _ => {}
+            }
+            bit += command.output_bits() as usize;
+        }
+        assert!(zero_runs.len() >= 3 && raw_runs.len() >= 3 && partial_bytes.len() >= 3);
+        [&zero_runs[..3], &raw_runs[..3], &partial_bytes[..3]].concat()
+    }
+
+    /// Feeds `compressed` to a `StreamDecoder` in small chunks, skipping the
+    /// first `count` bytes and reading the rest
+    fn stream_skip_then_read<const N: usize>(compressed: &[u8], count: usize) -> Result<(usize, Vec<u8>), DecoderError> {
+        let mut decoder = StreamDecoder::<N>::with_capacity();
+        let mut skipped = 0;
+        let mut output = Vec::new();
+        let mut buf = [0; 64];
+        let mut drain = |decoder: &mut StreamDecoder<N>| -> Result<(), DecoderError> {
+            while skipped < count {
+                match decoder.skip(count - skipped)? {
+                    0 => return Ok(()),
+                    n => skipped += n,
+                }
+            }
+            loop {
+                match decoder.read(&mut buf)? {
+                    0 => return Ok(()),
+                    n => output.extend_from_slice(&buf[..n]),
+                }
+            }
+        };
+        for chunk in compressed.chunks(7) {
+            assert_eq!(decoder.feed(chunk), chunk.len());
+            drain(&mut decoder)?;
+        }
+        decoder.finish();
+        drain(&mut decoder)?;
+        assert!(decoder.is_finished());
+        Ok((skipped, output))
+    }
+
+    fn check_skip<const N: usize>(compressed: &[u8], data: &[u8], offsets: &[usize]) {
+        for &k in offsets {
+            let mut decoder = Decoder::<N>::with_capacity(compressed);
+            assert_eq!(decoder.skip(k), Ok(k), "N = {}, skip({})", N, k);
+            assert_eq!(read_to_end(&mut decoder, 64).as_deref(), Ok(&data[k..]), "N = {}, skip({})", N, k);
+
+            // Skipping output that is already in the buffer
+            let mut decoder = Decoder::<N>::with_capacity(compressed);
+            assert_eq!(decoder.read(&mut [0; 1]), Ok(1));
+            assert_eq!(decoder.skip(k - 1), Ok(k - 1), "N = {}, read(1) + skip({})", N, k - 1);
+            assert_eq!(read_to_end(&mut decoder, 64).as_deref(), Ok(&data[k..]), "N = {}, read(1) + skip({})", N, k - 1);
+
+            assert_eq!(stream_skip_then_read::<N>(compressed, k), Ok((k, data[k..].to_vec())), "stream, N = {}, skip({})", N, k);
+        }
+
+        // Skipping past the end stops at the end of the stream
+        let mut decoder = Decoder::<N>::with_capacity(compressed);
+        assert_eq!(decoder.skip(data.len() + 100), Ok(data.len()));
+        assert_eq!(decoder.skip(1), Ok(0));
+        assert_eq!(stream_skip_then_read::<N>(compressed, data.len() + 100), Ok((data.len(), Vec::new())));
+
+        // Data after the end of the stream is reported once everything is skipped
+        let mut trailing = compressed.to_vec();
+        trailing.push(0);
+        let mut decoder = Decoder::<N>::with_capacity(&trailing);
+        assert_eq!(decoder.skip(usize::MAX), Ok(data.len()));
+        assert_eq!(decoder.skip(1).map_err(|error| error.kind()), Err(DecoderErrorKind::TrailingData));
+        let error = stream_skip_then_read::<N>(&trailing, usize::MAX).unwrap_err();
+        assert_eq!(error.kind(), DecoderErrorKind::TrailingData);
+    }
+
+    #[test]
+    fn skip() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+        let offsets = skip_offsets(&compressed);
+        check_skip::<1>(&compressed, &data, &offsets);
+        check_skip::<16>(&compressed, &data, &offsets);
+        check_skip::<256>(&compressed, &data, &offsets);
+    }
+
+    #[test]
+    fn fuzz_corpus() {
+        use DecoderErrorKind::*;
+
+        let corpus: &[(&[u8], Result<usize, DecoderErrorKind>)] = &[
+            (include_bytes!("../fuzz/corpus/decode/all_ones"), Err(InvalidHeader)),
+            (include_bytes!("../fuzz/corpus/decode/all_zeros"), Err(TrailingData)),
+            (include_bytes!("../fuzz/corpus/decode/bad_header"), Err(InvalidHeader)),
+            (include_bytes!("../fuzz/corpus/decode/empty"), Err(InvalidHeader)),
+            (include_bytes!("../fuzz/corpus/decode/empty_stream"), Ok(0)),
+            (include_bytes!("../fuzz/corpus/decode/header_only"), Err(UnexpectedEof)),
+            (include_bytes!("../fuzz/corpus/decode/long_zero_run"), Ok(1 << 20)),
+            (include_bytes!("../fuzz/corpus/decode/raw_run_then_finish"), Ok(0)),
+            (include_bytes!("../fuzz/corpus/decode/short_header"), Err(InvalidHeader)),
+            (include_bytes!("../fuzz/corpus/decode/trailing_data"), Err(TrailingData)),
+            (include_bytes!("../fuzz/corpus/decode/truncated_command"), Err(UnexpectedEof)),
+            (include_bytes!("../fuzz/corpus/decode/truncated_raw_run"), Err(UnexpectedEof)),
+            (include_bytes!("../fuzz/corpus/decode/unaligned_finish"), Ok(0)),
+        ];
+        for (i, (compressed, expected)) in corpus.iter().enumerate() {
+            let result = decode_all_ways(compressed).map_err(|error| error.kind());
+            assert_eq!(result, *expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn buffer_and_read_sizes() {
+        let data = sample_data();
+        for &trailer in &[false, true] {
+            let compressed = compress(&data, trailer);
+            // Reads shorter, equal to and longer than the output buffer
+            for &chunk in &[1, 3, 15, 16, 17, 255, 256, 257, 4096] {
+                assert_eq!(read_to_end(&mut Decoder::<1>::with_capacity(&compressed), chunk).as_ref(), Ok(&data), "N = 1, {}-byte reads", chunk);
+                assert_eq!(read_to_end(&mut Decoder::<16>::with_capacity(&compressed), chunk).as_ref(), Ok(&data), "N = 16, {}-byte reads", chunk);
+                assert_eq!(read_to_end(&mut Decoder::<256>::with_capacity(&compressed), chunk).as_ref(), Ok(&data), "N = 256, {}-byte reads", chunk);
+            }
+        }
+    }
+}
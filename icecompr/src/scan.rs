@@ -0,0 +1,87 @@
+use crate::{Decoder, DecoderError};
+
+/// Summary of a well-formed compressed stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanInfo {
+    /// Length of the decompressed data in bytes
+    pub decompressed_len: usize,
+    /// Number of commands, including the final one
+    pub commands: usize,
+    /// CRC-32 of the decompressed data if the stream has an integrity
+    /// trailer, which has been checked
+    pub crc32: Option<u32>,
+}
+
+/// Walks the command stream without keeping any output
+///
+/// Fails exactly where `Decoder` would fail on the same data, with the same
+/// error position. Zero runs are skipped arithmetically unless the stream
+/// has an integrity trailer, which needs the checksum of all output bytes.
+pub fn scan(compressed: &[u8]) -> Result<ScanInfo, DecoderError> {
+    let mut decoder = Decoder::<1>::with_capacity(compressed);
+    let mut decompressed_len = 0;
+    loop {
+        // Only returns 0 after checking for trailing data
+        match decoder.skip(usize::MAX)? {
+            0 => break,
+            n => decompressed_len += n,
+        }
+    }
+
+    Ok(ScanInfo {
+        decompressed_len,
+        commands: decoder.core.commands,
+        crc32: decoder.core.checksum.map(|checksum| checksum.crc()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::{compress, read_to_end, sample_data};
+    use crate::{Commands, Crc32};
+
+    #[test]
+    fn encoder_output() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+        let info = scan(&compressed).unwrap();
+        assert_eq!(info.decompressed_len, data.len());
+        assert_eq!(info.commands, Commands::new(&compressed).count());
+        assert_eq!(info.crc32, None);
+
+        let mut crc = Crc32::new();
+        crc.update(&data);
+        let compressed = compress(&data, true);
+        let info = scan(&compressed).unwrap();
+        assert_eq!(info.decompressed_len, data.len());
+        assert_eq!(info.crc32, Some(crc.finish()));
+    }
+
+    #[test]
+    fn same_errors_as_decoder() {
+        let compressed = compress(&sample_data(), false);
+        let decode = |compressed: &[u8]| read_to_end(&mut Decoder::new(compressed), 64).map(|output| output.len());
+
+        // Truncated inside the header, inside commands and before the padding
+        for len in (0..compressed.len()).step_by(7) {
+            let truncated = &compressed[..len];
+            assert!(decode(truncated).is_err(), "truncated to {} bytes", len);
+            assert_eq!(scan(truncated).map(|info| info.decompressed_len), decode(truncated), "truncated to {} bytes", len);
+        }
+
+        // Flipped bits change the commands, the positions of the resulting
+        // errors have to match as well
+        let mut errors = 0;
+        for bit in (0..compressed.len() * 8).step_by(13) {
+            let mut corrupted = compressed.clone();
+            corrupted[bit / 8] ^= 0x80 >> (bit % 8);
+            let expected = decode(&corrupted);
+            errors += expected.is_err() as usize;
+            assert_eq!(scan(&corrupted).map(|info| info.decompressed_len), expected, "bit {} flipped", bit);
+        }
+        assert!(errors > 0);
+    }
+}
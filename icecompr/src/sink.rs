@@ -0,0 +1,90 @@
+use crate::{Decoder, DecoderError};
+
+/// Error returned by sink-driven decompression
+#[derive(Debug, PartialEq)]
+pub enum SinkError<E> {
+    Decoder(DecoderError),
+    Sink(E),
+}
+
+impl<E> From<DecoderError> for SinkError<E> {
+    fn from(e: DecoderError) -> Self {
+        SinkError::Decoder(e)
+    }
+}
+
+impl<'a, const N: usize> Decoder<'a, N> {
+    /// Decompresses the rest of the stream into `sink`
+    ///
+    /// Data is passed to the sink in chunks of up to `N` bytes. Returns the
+    /// number of bytes written.
+    pub fn decode_to<E, F>(&mut self, mut sink: F) -> Result<usize, SinkError<E>>
+    where
+        F: FnMut(&[u8]) -> Result<(), E>,
+    {
+        let mut buf = [0; N];
+        let mut total = 0;
+        loop {
+            let n = self.read(&mut buf)?;
+            if n == 0 {
+                return Ok(total);
+            }
+            sink(&buf[..n]).map_err(SinkError::Sink)?;
+            total += n;
+        }
+    }
+
+    /// Decompresses the rest of the stream into an SPI bus
+    #[cfg(feature = "embedded-hal")]
+    pub fn decode_to_spi<SPI>(&mut self, spi: &mut SPI) -> Result<usize, SinkError<SPI::Error>>
+    where
+        SPI: embedded_hal::blocking::spi::Write<u8>,
+    {
+        self.decode_to(|chunk| spi.write(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::{compress, sample_data};
+    use std::vec::Vec;
+
+    #[test]
+    fn chunks() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+        let mut output = Vec::new();
+        let mut decoder = Decoder::<16>::with_capacity(&compressed);
+        let total = decoder.decode_to(|chunk| {
+            assert!(!chunk.is_empty() && chunk.len() <= 16);
+            output.extend_from_slice(chunk);
+            Ok::<(), ()>(())
+        });
+        assert_eq!(total, Ok(data.len()));
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn sink_error() {
+        let compressed = compress(&sample_data(), false);
+        let mut calls = 0;
+        let mut decoder = Decoder::<16>::with_capacity(&compressed);
+        let result = decoder.decode_to(|_| {
+            calls += 1;
+            if calls == 3 {
+                Err("full")
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result, Err(SinkError::Sink("full")));
+        assert_eq!(calls, 3);
+
+        // Decoding can go on after the chunk the sink rejected
+        let mut buf = [0; 16];
+        assert_eq!(decoder.read(&mut buf), Ok(16));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{Decoder, DecoderError, DEFAULT_BUFFER_SIZE};
+use core::convert::Infallible;
+
+/// Anything that produces a raw iCE40 bitstream
+///
+/// Loaders take a `BitstreamSource` and do not need to know whether or how
+/// the image is compressed.
+pub trait BitstreamSource {
+    type Error;
+
+    /// Reads bitstream data into `buf`, returns 0 at the end of the image
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Uncompressed bitstream stored in memory
+pub struct RawSource<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> RawSource<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl BitstreamSource for RawSource<'_> {
+    type Error = Infallible;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        let n = core::cmp::min(buf.len(), self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+impl<const N: usize> BitstreamSource for Decoder<'_, N> {
+    type Error = DecoderError;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DecoderError> {
+        Decoder::read(self, buf)
+    }
+}
+
+/// Source for an image in any of the supported formats
+pub enum AnySource<'a, const N: usize = DEFAULT_BUFFER_SIZE> {
+    Raw(RawSource<'a>),
+    Icecompr(Decoder<'a, N>),
+}
+
+impl<'a> AnySource<'a> {
+    /// Picks the format from the first bytes of `data`
+    ///
+    /// Returns `None` if the format is not recognized.
+    pub fn detect(data: &'a [u8]) -> Option<Self> {
+        Self::detect_with_capacity(data)
+    }
+}
+
+impl<'a, const N: usize> AnySource<'a, N> {
+    /// Same as `detect`, with an `N`-byte decoder output buffer
+    pub fn detect_with_capacity(data: &'a [u8]) -> Option<Self> {
+        if data.starts_with(b"ICECOMPR") {
+            Some(AnySource::Icecompr(Decoder::with_capacity(data)))
+        } else if data.starts_with(&[0xff, 0x00]) || data.starts_with(&[0x7e, 0xaa, 0x99, 0x7e]) {
+            // Comment block written by icepack, or the bare preamble
+            Some(AnySource::Raw(RawSource::new(data)))
+        } else {
+            None
+        }
+    }
+}
+
+impl<const N: usize> BitstreamSource for AnySource<'_, N> {
+    type Error = DecoderError;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DecoderError> {
+        match self {
+            AnySource::Raw(source) => match source.read(buf) {
+                Ok(n) => Ok(n),
+                Err(never) => match never {},
+            },
+            AnySource::Icecompr(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::{compress, sample_data};
+    use std::vec::Vec;
+
+    fn read_all<S: BitstreamSource>(source: &mut S) -> Vec<u8>
+    where
+        S::Error: core::fmt::Debug,
+    {
+        let mut output = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match source.read(&mut buf).unwrap() {
+                0 => return output,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn detect() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+        let mut source = AnySource::<16>::detect_with_capacity(&compressed).unwrap();
+        assert!(matches!(source, AnySource::Icecompr(_)));
+        assert_eq!(read_all(&mut source), read_all(&mut Decoder::<16>::with_capacity(&compressed)));
+        assert_eq!(read_all(&mut AnySource::<16>::detect_with_capacity(&compressed).unwrap()), data);
+
+        // Comment block of icepack and the bare preamble
+        let commented = [&[0xff, 0x00][..], b"comment\0", &[0x00, 0xff, 0x7e, 0xaa, 0x99, 0x7e, 0x01, 0x00]].concat();
+        let bare = [0x7e, 0xaa, 0x99, 0x7e, 0x92, 0x00, 0x00];
+        for raw in [&commented[..], &bare] {
+            let mut source = AnySource::<16>::detect_with_capacity(raw).unwrap();
+            assert!(matches!(source, AnySource::Raw(_)));
+            assert_eq!(read_all(&mut source), read_all(&mut RawSource::new(raw)));
+            assert_eq!(read_all(&mut AnySource::<16>::detect_with_capacity(raw).unwrap()), raw);
+        }
+
+        for unknown in [&b""[..], b"ICECOMP", &[0xff, 0x01], &[0x7e, 0xaa, 0x99], &data] {
+            assert!(AnySource::<16>::detect_with_capacity(unknown).is_none());
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{DecoderCore, DecoderError, DecoderReader, DEFAULT_BUFFER_SIZE};
+
+const INPUT_BUFFER_SIZE: usize = 64;
+
+/// Decoder for compressed data arriving in chunks
+///
+/// Compressed data is pushed with `feed`, decompressed data is pulled with
+/// `read`. Only a small input buffer is kept, so the whole compressed image
+/// does not have to be stored in memory.
+pub struct StreamDecoder<const N: usize = DEFAULT_BUFFER_SIZE> {
+    core: DecoderCore<N>,
+    buffer: [u8; INPUT_BUFFER_SIZE],
+    start: usize,
+    end: usize,
+    bits: u64,
+    bit_count: u8,
+    fed_bits: usize,
+    complete: bool,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::with_capacity()
+    }
+}
+
+impl<const N: usize> StreamDecoder<N> {
+    /// Creates a decoder with an `N`-byte output buffer
+    ///
+    /// An integrity trailer at the end of the stream is verified. Whether a
+    /// trailer follows is only known at the end of the stream, so the
+    /// checksum of all output is computed from the start and `skip` cannot
+    /// skip zero runs arithmetically. Streams without a trailer decode as
+    /// usual.
+    pub fn with_capacity() -> Self {
+        Self::with_core(DecoderCore::new(true))
+    }
+
+    /// Creates a decoder that skips the integrity trailer without checking it
+    ///
+    /// Saves computing the checksum, e.g. for images that are verified by
+    /// other means or only scanned with `skip`.
+    pub fn without_trailer_check() -> Self {
+        Self::with_core(DecoderCore::new(false))
+    }
+
+    fn with_core(core: DecoderCore<N>) -> Self {
+        Self {
+            core,
+            buffer: [0; INPUT_BUFFER_SIZE],
+            start: 0,
+            end: 0,
+            bits: 0,
+            bit_count: 0,
+            fed_bits: 0,
+            complete: false,
+        }
+    }
+
+    /// Appends compressed data to the input buffer
+    ///
+    /// Returns the number of bytes accepted, which can be less than
+    /// `chunk.len()` if the buffer is full. Call `read` to make room.
+    pub fn feed(&mut self, chunk: &[u8]) -> usize {
+        if self.start > 0 {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+
+        let n = core::cmp::min(chunk.len(), self.buffer.len() - self.end);
+        self.buffer[self.end..self.end + n].copy_from_slice(&chunk[..n]);
+        self.end += n;
+        n
+    }
+
+    /// Marks the end of the compressed data
+    ///
+    /// After this call, a stream that stops in the middle of a command is
+    /// reported as `DecoderErrorKind::UnexpectedEof`. Until then the decoder
+    /// cannot tell whether an integrity trailer follows the last command, so
+    /// it is not finished yet.
+    pub fn finish(&mut self) {
+        self.complete = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.core.is_finished()
+    }
+
+    /// Decompresses data into `buf`
+    ///
+    /// Returns 0 if the stream is finished or all buffered input is consumed.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, DecoderError> {
+        let complete = self.complete;
+        self.with_reader(|core, reader| core.read(reader, complete, buf))
+    }
+
+    /// Skips up to `count` bytes of decompressed data without producing them
+    ///
+    /// Returns the number of bytes skipped, which is less than `count` if the
+    /// stream is finished or all buffered input is consumed.
+    pub fn skip(&mut self, count: usize) -> Result<usize, DecoderError> {
+        let complete = self.complete;
+        self.with_reader(|core, reader| core.skip(reader, complete, count))
+    }
+
+    fn with_reader<T>(&mut self, f: impl FnOnce(&mut DecoderCore<N>, &mut DecoderReader) -> T) -> T {
+        let mut reader = DecoderReader {
+            bytes: &self.buffer[self.start..self.end],
+            bits: self.bits,
+            bit_count: self.bit_count,
+            fed_bits: self.fed_bits,
+        };
+
+        let result = f(&mut self.core, &mut reader);
+
+        self.start = self.end - reader.bytes.len();
+        self.bits = reader.bits;
+        self.bit_count = reader.bit_count;
+        self.fed_bits = reader.fed_bits;
+        result
+    }
+}
+
+impl<const N: usize> Default for StreamDecoder<N> {
+    fn default() -> Self {
+        Self::with_capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::tests::{compress, sample_data, stream};
+    use crate::{Decoder, DecoderErrorKind};
+    use std::vec;
+    use std::vec::Vec;
+
+    fn decode<const N: usize>(decoder: &mut StreamDecoder<N>, compressed: &[u8]) -> Result<Vec<u8>, DecoderError> {
+        decode_in_chunks(decoder, compressed, 5)
+    }
+
+    /// Feeds `compressed` in chunks of `chunk` bytes, reading everything in between
+    fn decode_in_chunks<const N: usize>(decoder: &mut StreamDecoder<N>, compressed: &[u8], chunk: usize) -> Result<Vec<u8>, DecoderError> {
+        let mut output = Vec::new();
+        let mut buf = [0; 32];
+        for chunk in compressed.chunks(chunk) {
+            let mut chunk = chunk;
+            while !chunk.is_empty() {
+                let n = decoder.feed(chunk);
+                chunk = &chunk[n..];
+                loop {
+                    match decoder.read(&mut buf)? {
+                        0 => break,
+                        n => output.extend_from_slice(&buf[..n]),
+                    }
+                }
+            }
+        }
+        decoder.finish();
+        loop {
+            match decoder.read(&mut buf)? {
+                0 => break,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert!(decoder.is_finished());
+        Ok(output)
+    }
+
+    #[test]
+    fn trailer() {
+        let data = sample_data();
+        let mut compressed = compress(&data, true);
+        assert_eq!(decode(&mut StreamDecoder::new(), &compressed), Ok(data.clone()));
+        assert_eq!(decode(&mut StreamDecoder::<16>::without_trailer_check(), &compressed), Ok(data.clone()));
+
+        // A corrupted trailer is noticed unless the check is turned off
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let error = decode(&mut StreamDecoder::new(), &compressed).unwrap_err();
+        assert_eq!(error.kind(), DecoderErrorKind::ChecksumMismatch);
+        let error = decode(&mut StreamDecoder::<16>::with_capacity(), &compressed).unwrap_err();
+        assert_eq!(error.kind(), DecoderErrorKind::ChecksumMismatch);
+        assert_eq!(decode(&mut StreamDecoder::<16>::without_trailer_check(), &compressed), Ok(data));
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let data = sample_data();
+        for &trailer in &[false, true] {
+            let compressed = compress(&data, trailer);
+            let mut expected = Vec::new();
+            let mut decoder = Decoder::new(&compressed);
+            let mut buf = [0; 64];
+            loop {
+                match decoder.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => expected.extend_from_slice(&buf[..n]),
+                }
+            }
+            assert_eq!(expected, data);
+
+            assert_eq!(decode_in_chunks(&mut StreamDecoder::new(), &compressed, 1), Ok(expected.clone()));
+            assert_eq!(decode_in_chunks(&mut StreamDecoder::<1>::with_capacity(), &compressed, 1), Ok(expected.clone()));
+            assert_eq!(decode_in_chunks(&mut StreamDecoder::<256>::with_capacity(), &compressed, 100), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn feed_backpressure() {
+        let data = sample_data();
+        let compressed = compress(&data, false);
+        let mut decoder = StreamDecoder::new();
+        assert_eq!(decoder.feed(&compressed), INPUT_BUFFER_SIZE);
+        assert_eq!(decoder.feed(&compressed[INPUT_BUFFER_SIZE..]), 0);
+
+        // Reading makes room for more input
+        let mut buf = [0; 4];
+        assert_eq!(decoder.read(&mut buf), Ok(4));
+        let accepted = decoder.feed(&compressed[INPUT_BUFFER_SIZE..]);
+        assert!(accepted > 0 && accepted < INPUT_BUFFER_SIZE);
+
+        let mut output = buf.to_vec();
+        let mut fed = INPUT_BUFFER_SIZE + accepted;
+        let mut buf = vec![0; 100];
+        while !decoder.is_finished() {
+            match decoder.read(&mut buf).unwrap() {
+                0 if fed == compressed.len() => decoder.finish(),
+                0 => fed += decoder.feed(&compressed[fed..]),
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        // Cut in the middle of the finish command and of a raw run
+        for bits in &["101 00000", "0001 000111 101"] {
+            let mut decoder = StreamDecoder::new();
+            let compressed = stream(bits);
+            assert_eq!(decoder.feed(&compressed), compressed.len());
+
+            // Without `finish` the decoder waits for more input
+            let mut buf = [0; 16];
+            while decoder.read(&mut buf).unwrap() > 0 {}
+            assert!(!decoder.is_finished());
+
+            decoder.finish();
+            let error = loop {
+                match decoder.read(&mut buf) {
+                    Ok(0) => panic!("{}: end of stream not detected", bits),
+                    Ok(_) => {}
+                    Err(error) => break error,
+                }
+            };
+            assert_eq!(error.kind(), DecoderErrorKind::UnexpectedEof, "{}", bits);
+        }
+    }
+}
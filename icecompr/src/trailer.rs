@@ -0,0 +1,135 @@
+//! Optional integrity trailer
+//!
+//! Encoders may append a trailer to the stream after the `Finish` command,
+//! starting at the next byte boundary:
+//!
+//! | Bytes | Content                                     |
+//! |-------|---------------------------------------------|
+//! | 4     | `ICRC`                                      |
+//! | 4     | Length of the decompressed data, big-endian |
+//! | 4     | CRC-32 of the decompressed data, big-endian |
+//!
+//! Streams without a trailer decode as before. Decoders that do not know
+//! about the trailer stop after the `Finish` command and ignore it.
+
+use crate::{Crc32, DecoderErrorKind, DecoderReader, Output};
+
+const MAGIC: &[u8; 4] = b"ICRC";
+
+pub(crate) const TRAILER_LEN: usize = 12;
+
+/// Builds the trailer for `data`
+pub(crate) fn trailer(data: &[u8]) -> [u8; TRAILER_LEN] {
+    let mut crc = Crc32::new();
+    crc.update(data);
+
+    let mut trailer = [0; TRAILER_LEN];
+    trailer[..4].copy_from_slice(MAGIC);
+    trailer[4..8].copy_from_slice(&(data.len() as u32).to_be_bytes());
+    trailer[8..].copy_from_slice(&crc.finish().to_be_bytes());
+    trailer
+}
+
+/// Whether complete compressed data ends with a trailer
+pub(crate) fn has_trailer(compressed: &[u8]) -> bool {
+    compressed.len() >= TRAILER_LEN && compressed[compressed.len() - TRAILER_LEN..].starts_with(MAGIC)
+}
+
+/// Whether `data`, starting at the byte after the `Finish` command, begins
+/// with a complete trailer
+pub(crate) fn starts_with_trailer(data: &[u8]) -> bool {
+    data.len() >= TRAILER_LEN && data.starts_with(MAGIC)
+}
+
+pub(crate) enum Trailer {
+    /// More input is needed to tell whether a trailer follows
+    Pending,
+    Absent,
+    Present { len: u32, crc: u32 },
+}
+
+/// Reads the trailer following the `Finish` command, if there is one
+///
+/// Data after the command that does not start with the magic is left in the
+/// reader for the trailing data check.
+pub(crate) fn read_trailer(reader: &mut DecoderReader, complete: bool) -> Result<Trailer, DecoderErrorKind> {
+    // Input arrives in whole bytes, so the padding is always available
+    let padding = (8 - reader.bit_offset() % 8) % 8;
+    if reader.skip_bits(padding).is_none() {
+        return Ok(Trailer::Absent);
+    }
+
+    match reader.peek_int(32) {
+        Some(magic) if magic == u32::from_be_bytes(*MAGIC) => {}
+        Some(_) => return Ok(Trailer::Absent),
+        None if complete => return Ok(Trailer::Absent),
+        None => return Ok(Trailer::Pending),
+    }
+    if reader.len_bits() < TRAILER_LEN * 8 {
+        if complete {
+            return Err(DecoderErrorKind::UnexpectedEof);
+        }
+        return Ok(Trailer::Pending);
+    }
+
+    reader.skip_bits(32);
+    let len = reader.read_int(32).unwrap_or(0);
+    let crc = reader.read_int(32).unwrap_or(0);
+    Ok(Trailer::Present { len, crc })
+}
+
+/// Running checksum of the decompressed data
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Checksum {
+    crc: Crc32,
+    len: usize,
+}
+
+impl Checksum {
+    pub(crate) fn new() -> Self {
+        Self { crc: Crc32::new(), len: 0 }
+    }
+
+    pub(crate) fn crc(&self) -> u32 {
+        self.crc.finish()
+    }
+
+    pub(crate) fn verify(&self, len: u32, crc: u32) -> Result<(), DecoderErrorKind> {
+        if self.len != len as usize {
+            return Err(DecoderErrorKind::LengthMismatch);
+        }
+        if self.crc() != crc {
+            return Err(DecoderErrorKind::ChecksumMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Output that feeds everything written to it into a checksum
+pub(crate) struct ChecksumOutput<'o, O> {
+    pub(crate) out: &'o mut O,
+    pub(crate) checksum: &'o mut Checksum,
+}
+
+impl<O: Output> Output for ChecksumOutput<'_, O> {
+    fn space(&self) -> usize {
+        self.out.space()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.checksum.crc.update(&[byte]);
+        self.checksum.len += 1;
+        self.out.write_byte(byte);
+    }
+
+    fn write_zero_bytes(&mut self, count: usize) {
+        let mut remaining = count;
+        while remaining > 0 {
+            let n = core::cmp::min(remaining, 32);
+            self.checksum.crc.update(&[0; 32][..n]);
+            remaining -= n;
+        }
+        self.checksum.len += count;
+        self.out.write_zero_bytes(count);
+    }
+}
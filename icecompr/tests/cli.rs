@@ -0,0 +1,68 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn icecompr(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_icecompr"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Sparse data with a few set bits, like a bitstream
+fn sample_data() -> Vec<u8> {
+    let mut data = vec![0; 10000];
+    for i in (0..data.len()).step_by(37) {
+        data[i] = (i % 251) as u8;
+    }
+    data
+}
+
+fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("icecompr-{}-{}", std::process::id(), name));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn stdin_to_stdout() {
+    let data = sample_data();
+    for args in [&["compress"][..], &["compress", "--trailer", "-", "-"]] {
+        let compressed = icecompr(args, &data);
+        assert!(compressed.status.success(), "{:?}", args);
+        assert!(compressed.stdout.starts_with(b"ICECOMPR"));
+        assert!(compressed.stdout.len() < data.len() / 4);
+
+        let decompressed = icecompr(&["decompress"], &compressed.stdout);
+        assert!(decompressed.status.success(), "{:?}", args);
+        assert_eq!(decompressed.stdout, data);
+    }
+}
+
+#[test]
+fn verify() {
+    let data = sample_data();
+    let mut compressed = vec![0; data.len()];
+    let n = icecompr::compress(&data, &mut compressed).unwrap();
+    let input = temp_file("input", &data);
+    let compressed = temp_file("compressed", &compressed[..n]);
+    let mut other = data.clone();
+    other[100] ^= 0x10;
+    let other = temp_file("other", &other);
+
+    let output = icecompr(&["verify", input.to_str().unwrap(), compressed.to_str().unwrap()], &[]);
+    assert!(output.status.success());
+
+    let output = icecompr(&["verify", other.to_str().unwrap(), compressed.to_str().unwrap()], &[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "icecompr: data differs at byte 100\n");
+
+    for path in [input, compressed, other] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
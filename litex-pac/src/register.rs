@@ -177,7 +177,11 @@ impl<T: Copy> UnsafeWORegister<T> {
 /// * a reference to the instance of that peripheral: 'gpioa' (anything which dereferences to
 ///   `RegisterBlock`, such as `Instance`, `&Instance`, `&RegisterBlock`, or
 ///   `*const RegisterBlock`),
-/// * the register you wish you access: `MODER` (a field on the `RegisterBlock`).
+/// * the register you wish you access: `MODER` (a field on the `RegisterBlock`),
+///   or an element of a register array, such as `CH[1]`.
+///
+/// Registers inside a cluster are reached through the cluster's module and a reference to the
+/// cluster itself, for example `stm32ral::dma::CH` and `&dma1.CH[1]`.
 ///
 /// In the single-value usage, the final argument is just the value to write:
 /// ```rust,no_run
@@ -249,18 +253,18 @@ impl<T: Copy> UnsafeWORegister<T> {
 /// and the macro brings such constants into scope and then dereferences the provided reference.
 #[macro_export]
 macro_rules! write_reg {
-    ( $periph:path, $instance:expr, $reg:ident, $( $field:ident : $value:expr ),+ ) => {{
+    ( $periph:path, $instance:expr, $reg:ident $([$index:expr])?, $( $field:ident : $value:expr ),+ ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
         #[allow(unused_imports)]
-        (*$instance).$reg.write(
+        (*$instance).$reg $([$index])?.write(
             $({ use $periph::{$reg::$field::{mask, offset, W::*, RW::*}}; ($value << offset) & mask }) | *
         );
     }};
-    ( $periph:path, $instance:expr, $reg:ident, $value:expr ) => {{
+    ( $periph:path, $instance:expr, $reg:ident $([$index:expr])?, $value:expr ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
-        (*$instance).$reg.write($value);
+        (*$instance).$reg $([$index])?.write($value);
     }};
 }
 
@@ -292,7 +296,11 @@ macro_rules! write_reg {
 /// * a reference to the instance of that peripheral: 'gpioa' (anything which dereferences to
 ///   `RegisterBlock`, such as `Instance`, `&Instance`, `&RegisterBlock`, or
 ///   `*const RegisterBlock`),
-/// * the register you wish you access: `MODER` (a field on the `RegisterBlock`).
+/// * the register you wish you access: `MODER` (a field on the `RegisterBlock`),
+///   or an element of a register array, such as `CH[1]`.
+///
+/// Registers inside a cluster are reached through the cluster's module and a reference to the
+/// cluster itself, for example `stm32ral::dma::CH` and `&dma1.CH[1]`.
 ///
 /// In the whole-register usage, the final argument is a closure that accepts the current value
 /// of the register and returns the new value to write:
@@ -378,18 +386,20 @@ macro_rules! write_reg {
 /// and the macro brings such constants into scope and then dereferences the provided reference.
 #[macro_export]
 macro_rules! modify_reg {
-    ( $periph:path, $instance:expr, $reg:ident, $( $field:ident : $value:expr ),+ ) => {{
+    ( $periph:path, $instance:expr, $reg:ident $([$index:expr])?, $( $field:ident : $value:expr ),+ ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
+        let register = &(*$instance).$reg $([$index])?;
         #[allow(unused_imports)]
-        (*$instance).$reg.write(
-            ((*$instance).$reg.read() & !( $({ use $periph::{$reg::$field::mask}; mask }) | * ))
+        register.write(
+            (register.read() & !( $({ use $periph::{$reg::$field::mask}; mask }) | * ))
             | $({ use $periph::{$reg::$field::{mask, offset, W::*, RW::*}}; ($value << offset) & mask }) | *);
     }};
-    ( $periph:path, $instance:expr, $reg:ident, $fn:expr ) => {{
+    ( $periph:path, $instance:expr, $reg:ident $([$index:expr])?, $fn:expr ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
-        (*$instance).$reg.write($fn((*$instance).$reg.read()));
+        let register = &(*$instance).$reg $([$index])?;
+        register.write($fn(register.read()));
     }};
 }
 
@@ -427,7 +437,11 @@ macro_rules! modify_reg {
 /// * a reference to the instance of that peripheral: 'gpioa' (anything which dereferences to
 ///   `RegisterBlock`, such as `Instance`, `&Instance`, `&RegisterBlock`, or
 ///   `*const RegisterBlock`),
-/// * the register you wish to access: `IDR` (a field on the `RegisterBlock`).
+/// * the register you wish to access: `IDR` (a field on the `RegisterBlock`),
+///   or an element of a register array, such as `CH[1]`.
+///
+/// Registers inside a cluster are reached through the cluster's module and a reference to the
+/// cluster itself, for example `stm32ral::dma::CH` and `&dma1.CH[1]`.
 ///
 /// In the whole-register usage, the macro simply returns the register's value:
 /// ```rust,no_run
@@ -492,27 +506,27 @@ macro_rules! modify_reg {
 /// and the macro brings such constants into scope and then dereferences the provided reference.
 #[macro_export]
 macro_rules! read_reg {
-    ( $periph:path, $instance:expr, $reg:ident, $( $field:ident ),+ ) => {{
+    ( $periph:path, $instance:expr, $reg:ident $([$index:expr])?, $( $field:ident ),+ ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
-        let val = ((*$instance).$reg.read());
+        let val = ((*$instance).$reg $([$index])?.read());
         ( $({
             #[allow(unused_imports)]
             use $periph::{$reg::$field::{mask, offset, R::*, RW::*}};
             (val & mask) >> offset
         }) , *)
     }};
-    ( $periph:path, $instance:expr, $reg:ident, $field:ident $($cmp:tt)* ) => {{
+    ( $periph:path, $instance:expr, $reg:ident $([$index:expr])?, $field:ident $($cmp:tt)* ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
         #[allow(unused_imports)]
         use $periph::{$reg::$field::{mask, offset, R::*, RW::*}};
-        (((*$instance).$reg.read() & mask) >> offset) $($cmp)*
+        (((*$instance).$reg $([$index])?.read() & mask) >> offset) $($cmp)*
     }};
-    ( $periph:path, $instance:expr, $reg:ident ) => {{
+    ( $periph:path, $instance:expr, $reg:ident $([$index:expr])? ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
-        ((*$instance).$reg.read())
+        ((*$instance).$reg $([$index])?.read())
     }};
 }
 
@@ -543,7 +557,19 @@ macro_rules! read_reg {
 ///   `RegisterBlock`, such as `Instance`, `&Instance`, `&RegisterBlock`, or
 ///   `*const RegisterBlock`),
 /// * the module for the instance of that peripheral: `GPIOA`,
-/// * the register you wish to access: `MODER` (a field on the `RegisterBlock`).
+/// * the register you wish to access: `MODER` (a field on the `RegisterBlock`),
+///   or an element of a register array, such as `CH[1]`.
+///
+/// Registers inside a cluster are reached through the cluster's module and a reference to the
+/// cluster itself, like in `write_reg!`. In place of the instance module, the reset values of the
+/// cluster are given, for example `stm32ral::dma::DMA1::reset.CH[1]`:
+/// ```rust,no_run
+/// # use stm32ral::{read_reg, write_reg, modify_reg, reset_reg}; fn main() {
+/// # let dma1 = stm32ral::dma::DMA1::take().unwrap();
+/// // Reset the configuration of DMA1 channel 1
+/// reset_reg!(stm32ral::dma::CH, &dma1.CH[1], stm32ral::dma::DMA1::reset.CH[1], CR);
+/// # }
+/// ```
 ///
 /// In the whole-register usage, that's it:
 /// ```rust,no_run
@@ -589,20 +615,37 @@ macro_rules! read_reg {
 /// `GPIOA` they are not the same thing.
 #[macro_export]
 macro_rules! reset_reg {
-    ( $periph:path, $instance:expr, $instancemod:path, $reg:ident, $( $field:ident ),+ ) => {{
+    ( $periph:path, $instance:expr, $instancemod:path, $reg:ident $([$index:expr])?, $( $field:ident ),+ ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
         use $periph::{$instancemod::{reset}};
+        let register = &(*$instance).$reg $([$index])?;
         #[allow(unused_imports)]
-        (*$instance).$reg.write({
+        register.write({
             let resetmask: u32 = $({ use $periph::{$reg::$field::mask}; mask }) | *;
-            ((*$instance).$reg.read() & !resetmask) | (reset.$reg & resetmask)
+            (register.read() & !resetmask) | (reset.$reg $([$index])? & resetmask)
         });
     }};
-    ( $periph:path, $instance:expr, $instancemod:path, $reg:ident ) => {{
+    ( $periph:path, $instance:expr, $instancemod:path, $reg:ident $([$index:expr])? ) => {{
         #[allow(unused_imports)]
         use $periph::{*};
         use $periph::{$instancemod::{reset}};
-        (*$instance).$reg.write(reset.$reg);
+        (*$instance).$reg $([$index])?.write(reset.$reg $([$index])?);
+    }};
+    ( $periph:path, $instance:expr, $reset:expr, $reg:ident $([$index:expr])?, $( $field:ident ),+ ) => {{
+        #[allow(unused_imports)]
+        use $periph::{*};
+        let reset = &$reset;
+        let register = &(*$instance).$reg $([$index])?;
+        #[allow(unused_imports)]
+        register.write({
+            let resetmask: u32 = $({ use $periph::{$reg::$field::mask}; mask }) | *;
+            (register.read() & !resetmask) | (reset.$reg $([$index])? & resetmask)
+        });
+    }};
+    ( $periph:path, $instance:expr, $reset:expr, $reg:ident $([$index:expr])? ) => {{
+        #[allow(unused_imports)]
+        use $periph::{*};
+        (*$instance).$reg $([$index])?.write($reset.$reg $([$index])?);
     }};
 }
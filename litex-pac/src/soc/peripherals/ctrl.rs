@@ -61,6 +61,7 @@ pub mod BUS_ERRORS {
     
     }}
 
+#[repr(C)]
 pub struct RegisterBlock {
     /// Write a ``1`` to this register to reset the SoC.
     pub RESET: RWRegister<u32>,
@@ -74,6 +75,12 @@ pub struct RegisterBlock {
     pub BUS_ERRORS: RORegister<u32>,
 }
 
+const _: () = {
+    assert!(::core::mem::offset_of!(RegisterBlock, RESET) == 0x0);
+    assert!(::core::mem::offset_of!(RegisterBlock, SCRATCH) == 0x4);
+    assert!(::core::mem::offset_of!(RegisterBlock, BUS_ERRORS) == 0x8);
+};
+
 pub struct ResetValues {
     pub RESET: u32,
     pub SCRATCH: u32,
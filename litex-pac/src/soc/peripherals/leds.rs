@@ -87,10 +87,15 @@ pub mod OUT {
     
     }}
 
+#[repr(C)]
 pub struct RegisterBlock {
     pub OUT: RWRegister<u32>,
 }
 
+const _: () = {
+    assert!(::core::mem::offset_of!(RegisterBlock, OUT) == 0x0);
+};
+
 pub struct ResetValues {
     pub OUT: u32,
 }
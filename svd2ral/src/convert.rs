@@ -1,72 +1,71 @@
-use svd_parser::{Device, Peripheral, RegisterProperties, RegisterCluster, Register, RegisterInfo};
+use svd_parser::{Cluster, Device, DimElement, EnumeratedValues, Field, Peripheral, RegisterProperties, RegisterCluster, Register, RegisterInfo, Usage};
 use crate::types::*;
+use anyhow::{bail, Result};
 
 
-pub fn convert(device: &Device) -> ModelDevice {
+pub fn convert(device: &Device, ignore: &[&str]) -> Result<ModelDevice> {
     let mut peripherals = Vec::new();
     let mut instances = Vec::new();
 
     for peripheral in &device.peripherals {
-        let default_register_properties = peripheral.default_register_properties.merge(&device.default_register_properties);
+        if ignore.contains(&peripheral.name.as_str()) {
+            continue;
+        }
 
-        let (p, i) = convert_peripheral(peripheral, &default_register_properties);
+        let chain = derivation_chain(device, peripheral)?;
+        let (p, i) = convert_peripheral(&chain, &device.default_register_properties)?;
         peripherals.push(p);
         instances.push(i);
     }
 
-    ModelDevice {
+    Ok(ModelDevice {
         peripherals,
         instances,
+    })
+}
+
+/// The peripheral followed by the peripherals it is `derivedFrom`, transitively
+fn derivation_chain<'a>(device: &'a Device, peripheral: &'a Peripheral) -> Result<Vec<&'a Peripheral>> {
+    let mut chain = vec![peripheral];
+    let mut current = peripheral;
+    while let Some(base_name) = current.derived_from.as_ref() {
+        let base = match device.peripherals.iter().find(|p| &p.name == base_name) {
+            Some(base) => base,
+            None => bail!("{}: derived from unknown peripheral {}", current.name, base_name),
+        };
+        if chain.iter().any(|p| p.name == base.name) {
+            bail!("{}: circular derivedFrom", peripheral.name);
+        }
+        chain.push(base);
+        current = base;
     }
+    Ok(chain)
 }
 
-pub fn convert_peripheral(peripheral: &Peripheral, default_register_properties: &RegisterProperties) -> (ModelPeripheral, ModelPeripheralInstance) {
-    assert!(peripheral.derived_from.is_none(), "Derived peripherals are not supported");
+/// Converts a peripheral, inheriting missing elements from the peripherals
+/// it is derived from
+///
+/// The instance always keeps its own name and base address.
+pub fn convert_peripheral(chain: &[&Peripheral], device_register_properties: &RegisterProperties) -> Result<(ModelPeripheral, ModelPeripheralInstance)> {
+    let peripheral = chain[0];
 
-    let doc = if let Some(description) = peripheral.description.as_ref() {
-        description
-    } else {
-        &peripheral.name
-    };
+    let doc = chain.iter().find_map(|p| p.description.as_ref()).unwrap_or(&peripheral.name);
 
     let module_name = peripheral.name.to_ascii_lowercase();
 
-    let mut registers = Vec::new();
-    let mut reset_values = Vec::new();
-
-    if let Some(regs) = peripheral.registers.as_ref() {
-        for register_or_cluster in regs {
-            let register = match register_or_cluster {
-                RegisterCluster::Register(register) => register,
-                RegisterCluster::Cluster(_) => unimplemented!("register clusters are not supported"),
-            };
-    
-            let info = match register {
-                Register::Single(info) => info,
-                Register::Array(_, _) => unimplemented!("register arrays are not supported"),
-            };
-
-            let mut info = info.clone();
-            info.update_properties(default_register_properties);
-
-            let register = convert_register(&info);
-
-            let reset_value = ResetValue {
-                register: register.name.clone(),
-                value: register.properties.reset_value,
-            };
-
-            registers.push(register);
-            reset_values.push(reset_value);
-        }
-    }
-    
+    let default_register_properties = chain.iter().rev().fold(*device_register_properties, |properties, p| {
+        p.default_register_properties.merge(&properties)
+    });
+    let children = chain.iter().find_map(|p| p.registers.as_deref()).unwrap_or_default();
+    let (registers, clusters) = convert_children(children, &default_register_properties)?;
+    let reset_values = collect_reset_values(&registers, &clusters);
 
     let p = ModelPeripheral {
         name: peripheral.name.clone(),
         description: doc.to_string(),
         module_name: module_name.clone(),
         registers,
+        clusters,
     };
     let i = ModelPeripheralInstance {
         name: peripheral.name.clone(),
@@ -76,39 +75,238 @@ pub fn convert_peripheral(peripheral: &Peripheral, default_register_properties:
         base_address: peripheral.base_address,
         reset_values,
     };
-    (p, i)
+    Ok((p, i))
 }
 
-fn convert_register(register: &RegisterInfo) -> FinalRegisterInfo {
+fn convert_children(children: &[RegisterCluster], default_register_properties: &RegisterProperties) -> Result<(Vec<FinalRegisterInfo>, Vec<ModelCluster>)> {
+    let mut registers = Vec::new();
+    let mut clusters = Vec::new();
+    for register_or_cluster in children {
+        match register_or_cluster {
+            RegisterCluster::Register(register) => registers.extend(expand_register(register, default_register_properties)?),
+            RegisterCluster::Cluster(cluster) => clusters.push(convert_cluster(cluster, default_register_properties)?),
+        }
+    }
+    Ok((registers, clusters))
+}
+
+/// Converts a cluster into a nested register block
+///
+/// Like register arrays, arrays named `NAME[%s]` become a single indexed
+/// field, the block is padded to `dimIncrement` for that. Other arrays are
+/// expanded into individual fields of the same block type.
+fn convert_cluster(cluster: &Cluster, default_register_properties: &RegisterProperties) -> Result<ModelCluster> {
+    let default_register_properties = cluster.default_register_properties.merge(default_register_properties);
+    let (registers, clusters) = convert_children(&cluster.children, &default_register_properties)?;
+
+    let name = cluster.name.replace("[%s]", "").replace("%s", "");
+    let mut model = ModelCluster {
+        module_name: cluster.header_struct_name.clone().unwrap_or_else(|| name.clone()),
+        description: cluster.description.as_ref().map(|description| description.replace("%s", "n")),
+        registers,
+        clusters,
+        fields: Vec::new(),
+        stride: None,
+    };
+
+    let dim = match cluster {
+        Cluster::Single(_) => {
+            model.fields.push(ClusterField { name, address_offset: cluster.address_offset, dim: None });
+            return Ok(model);
+        }
+        Cluster::Array(_, dim) => dim,
+    };
+
+    if cluster.name.contains("[%s]") && is_numbered(dim) {
+        model.fields.push(ClusterField { name, address_offset: cluster.address_offset, dim: Some(dim.dim) });
+        model.stride = Some(dim.dim_increment);
+        return Ok(model);
+    }
+
+    for (i, index) in dim_indices(dim).iter().enumerate() {
+        model.fields.push(ClusterField {
+            name: cluster.name.replace("[%s]", index).replace("%s", index),
+            address_offset: cluster.address_offset + i as u32 * dim.dim_increment,
+            dim: None,
+        });
+    }
+    Ok(model)
+}
+
+/// Reset values of a register block, in the order of `ResetValues`
+fn collect_reset_values(registers: &[FinalRegisterInfo], clusters: &[ModelCluster]) -> Vec<ResetValue> {
+    let mut reset_values = Vec::new();
+    for register in registers {
+        reset_values.push(ResetValue::Register {
+            name: register.name.clone(),
+            value: register.properties.reset_value,
+            dim: register.dim,
+        });
+    }
+    for cluster in clusters {
+        for field in &cluster.fields {
+            reset_values.push(ResetValue::Cluster {
+                name: field.name.clone(),
+                module_name: cluster.module_name.clone(),
+                values: collect_reset_values(&cluster.registers, &cluster.clusters),
+                dim: field.dim,
+            });
+        }
+    }
+    reset_values
+}
+
+/// Converts a register, or each element of a register array
+///
+/// Arrays named `NAME[%s]` whose elements are packed back to back become a
+/// single indexed register, all other arrays are expanded into individual
+/// registers named after `dimIndex`.
+fn expand_register(register: &Register, default_register_properties: &RegisterProperties) -> Result<Vec<FinalRegisterInfo>> {
+    let mut info = (**register).clone();
+    info.update_properties(default_register_properties);
+
+    let dim = match register {
+        Register::Single(_) => return Ok(vec![convert_register(&info)?]),
+        Register::Array(_, dim) => dim,
+    };
+
+    if info.name.contains("[%s]") && is_indexable(&info, dim) {
+        info.name = info.name.replace("[%s]", "");
+        if let Some(description) = info.description.as_mut() {
+            *description = description.replace("%s", "n");
+        }
+        let mut register = convert_register(&info)?;
+        register.dim = Some(dim.dim);
+        return Ok(vec![register]);
+    }
+
+    dim_indices(dim).iter().enumerate().map(|(i, index)| {
+        let mut element = info.clone();
+        element.name = info.name.replace("[%s]", index).replace("%s", index);
+        element.description = info.description.as_ref().map(|description| description.replace("%s", index));
+        element.address_offset = info.address_offset + i as u32 * dim.dim_increment;
+        convert_register(&element)
+    }).collect()
+}
+
+/// Whether an array can be a Rust array, with elements numbered from 0
+fn is_indexable(info: &RegisterInfo, dim: &DimElement) -> bool {
+    is_numbered(dim) && info.size.map(|size| size / 8) == Some(dim.dim_increment)
+}
+
+fn is_numbered(dim: &DimElement) -> bool {
+    match dim.dim_index.as_ref() {
+        Some(indices) => indices.iter().enumerate().all(|(i, index)| *index == i.to_string()),
+        None => true,
+    }
+}
+
+/// Names of the array elements, from `dimIndex` or numbered from 0
+fn dim_indices(dim: &DimElement) -> Vec<String> {
+    match dim.dim_index.as_ref() {
+        Some(indices) => indices.clone(),
+        None => (0..dim.dim).map(|i| i.to_string()).collect(),
+    }
+}
+
+fn convert_register(register: &RegisterInfo) -> Result<FinalRegisterInfo> {
     let mut final_fields = Vec::new();
     if let Some(fields) = register.fields.as_ref() {
         for field in fields {
-            let final_field = FinalFieldInfo {
+            let mut final_field = FinalFieldInfo {
                 name: field.name.clone(),
                 description: field.description.clone(),
                 bit_range: field.bit_range,
-                access: field.access.or(register.access).unwrap(),
+                access: match field.access.or(register.access) {
+                    Some(access) => access,
+                    None => bail!("{}.{}: no access", register.name, field.name),
+                },
+                read_values: Vec::new(),
+                write_values: Vec::new(),
+                read_write_values: Vec::new(),
             };
+
+            for enumerated_values in &field.enumerated_values {
+                let usage = enumerated_values.usage;
+                let enumerated_values = resolve_enumerated_values(field, enumerated_values, fields)?;
+                let values = match usage.or(enumerated_values.usage) {
+                    Some(Usage::Read) => &mut final_field.read_values,
+                    Some(Usage::Write) => &mut final_field.write_values,
+                    Some(Usage::ReadWrite) | None => &mut final_field.read_write_values,
+                };
+                for value in &enumerated_values.values {
+                    // Default values cover everything else and have no value of their own
+                    let number = match value.value {
+                        Some(number) => number,
+                        None => continue,
+                    };
+                    let name = value_name(&value.name);
+                    if values.iter().any(|existing: &FinalEnumeratedValue| existing.name == name) {
+                        continue;
+                    }
+                    values.push(FinalEnumeratedValue {
+                        name,
+                        description: value.description.clone(),
+                        value: number,
+                    });
+                }
+            }
             final_fields.push(final_field);
         }
     }
 
-    let properties = FinalRegisterProperties {
-        size: register.size.unwrap(),
-        reset_value: register.reset_value.unwrap() as u64,
-        reset_mask: register.reset_mask.unwrap() as u64,
-        access: register.access.unwrap(),
+    let properties = match (register.size, register.reset_value, register.reset_mask, register.access) {
+        (Some(size), Some(reset_value), Some(reset_mask), Some(access)) => FinalRegisterProperties {
+            size,
+            reset_value: reset_value as u64,
+            reset_mask: reset_mask as u64,
+            access,
+        },
+        (None, ..) => bail!("{}: no size", register.name),
+        (_, None, ..) => bail!("{}: no resetValue", register.name),
+        (_, _, None, _) => bail!("{}: no resetMask", register.name),
+        (.., None) => bail!("{}: no access", register.name),
     };
 
     let final_info = FinalRegisterInfo {
         name: register.name.clone(),
         description: register.description.clone(),
         address_offset: register.address_offset,
+        dim: None,
         properties,
         fields: final_fields,
     };
 
-    final_info
+    Ok(final_info)
+}
+
+/// Follows `derivedFrom` to the values of another field of the same register
+///
+/// References may be qualified as `field.values`, only the last component is
+/// looked up.
+fn resolve_enumerated_values<'a>(field: &Field, enumerated_values: &'a EnumeratedValues, fields: &'a [Field]) -> Result<&'a EnumeratedValues> {
+    let derived_from = match enumerated_values.derived_from.as_ref() {
+        Some(derived_from) => derived_from,
+        None => return Ok(enumerated_values),
+    };
+    let name = derived_from.rsplit('.').next().unwrap_or(derived_from);
+    let found = fields.iter()
+        .flat_map(|field| &field.enumerated_values)
+        .find(|candidate| candidate.derived_from.is_none() && candidate.name.as_deref() == Some(name));
+    match found {
+        Some(found) => Ok(found),
+        None => bail!("{}: enumeratedValues derived from unknown {}", field.name, derived_from),
+    }
+}
+
+/// Turns an enumerated value name into a valid Rust identifier
+fn value_name(name: &str) -> String {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
 }
 
 trait RegisterPropertiesExt {
@@ -1,9 +1,9 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
 use std::fs;
 use std::io::Write;
 use std::fmt::Write as _;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 mod convert;
 
@@ -76,14 +76,21 @@ pub(crate) mod instances;
 pub mod metadata;
 ")?;
 
-    let device = crate::convert::convert(&device);
+    let device = crate::convert::convert(&device, ignore)?;
 
     let mut peripheral_modules = Vec::new();
     let mut instance_modules = Vec::new();
     let mut instance_names = Vec::new();
 
+    // Peripherals with identical register blocks, including those derived
+    // from another peripheral, share the module of the first one
+    let mut blocks: Vec<(&str, Block)> = Vec::new();
+    let mut shared_modules = BTreeMap::new();
+
     for peripheral in &device.peripherals {
-        if ignore.iter().find(|v| *v == &peripheral.name).is_some() {
+        let block = build_block(&peripheral.name, &peripheral.registers, &peripheral.clusters, None)?;
+        if let Some((module_name, _)) = blocks.iter().find(|(_, existing)| existing.code == block.code) {
+            shared_modules.insert(peripheral.module_name.as_str(), *module_name);
             continue;
         }
 
@@ -93,23 +100,21 @@ pub mod metadata;
         let file_name = format!("{}.rs", peripheral.module_name);
         let mut peripheral_rs = fs::File::create(peripherals_dir.join(&file_name))?;
 
-        write_peripheral(&mut peripheral_rs, peripheral, address_size)?;
+        write_peripheral(&mut peripheral_rs, peripheral, &block, address_size)?;
 
         peripheral_modules.push(peripheral.module_name.clone());
+        blocks.push((&peripheral.module_name, block));
     }
 
     for instance in &device.instances {
-        if ignore.iter().find(|v| *v == &instance.name).is_some() {
-            continue;
-        }
-
+        let peripheral_module = shared_modules.get(instance.peripheral_module.as_str()).copied().unwrap_or(&instance.peripheral_module);
         writeln!(instances_mod_rs, "pub mod {};", instance.module_name)?;
         writeln!(mod_rs, "pub use self::instances::{};", instance.module_name)?;
 
         let file_name = format!("{}.rs", instance.module_name);
         let mut instance_rs = fs::File::create(instances_dir.join(&file_name))?;
 
-        write_peripheral_instance(&mut instance_rs, instance)?;
+        write_peripheral_instance(&mut instance_rs, instance, peripheral_module)?;
 
         instance_modules.push(instance.module_name.clone());
         instance_names.push(instance.name.clone());
@@ -134,25 +139,61 @@ pub mod metadata;
     Ok(())
 }
 
-fn write_peripheral(file: &mut fs::File, peripheral: &ModelPeripheral, address_size: AddressSize) -> Result<()> {
+fn write_peripheral(file: &mut fs::File, peripheral: &ModelPeripheral, block: &Block, address_size: AddressSize) -> Result<()> {
     writeln!(file, "#![allow(non_snake_case, non_upper_case_globals)]")?;
     writeln!(file, "#![allow(non_camel_case_types)]")?;
     writeln!(file, "{}", build_doc_comment("//!", &peripheral.description))?;
 
+    writeln!(file, "use crate::{{{}}};", block.access_types.join(", "))?;
+    writeln!(file, "use core::marker::PhantomData;\n")?;
+
+    write!(file, "{}", block.code)?;
+
+    writeln!(file, "
+pub struct Instance {{
+    pub(crate) addr: {},
+    pub(crate) _marker: PhantomData<*const RegisterBlock>,
+}}
+
+impl ::core::ops::Deref for Instance {{
+    type Target = RegisterBlock;
+    #[inline(always)]
+    fn deref(&self) -> &RegisterBlock {{
+        unsafe {{ &*(self.addr as *const _) }}
+    }}
+}}", address_size.type_name())?;
+
+    Ok(())
+}
+
+/// Generated register block with its register modules
+struct Block {
+    /// Register types used by the block itself, not by nested clusters
+    access_types: Vec<String>,
+    code: String,
+    layout: Layout,
+}
+
+/// Size and alignment of a register block
+#[derive(Clone, Copy)]
+struct Layout {
+    size: u32,
+    align: u32,
+}
+
+/// Generates the register modules, `RegisterBlock` and `ResetValues`
+fn build_block(context: &str, registers: &[FinalRegisterInfo], clusters: &[ModelCluster], stride: Option<u32>) -> Result<Block> {
     let mut register_modules = Vec::new();
-    let mut register_block = Vec::new();
+    let mut block_fields = Vec::new();
     let mut reset_values = Vec::new();
     let mut access_types = BTreeSet::new();
-    let mut register_types = Vec::new();
 
-    for reg_info in &peripheral.registers {
+    for reg_info in registers {
         let access_type_name = reg_info.properties.access_type_name();
         access_types.insert(access_type_name);
 
         let size_type_name = reg_info.properties.size_type_name();
 
-        register_types.push(reg_info.name.clone());
-
         // Register module
         let mut code = String::new();
         if let Some(description) = reg_info.description.as_ref() {
@@ -169,63 +210,164 @@ fn write_peripheral(file: &mut fs::File, peripheral: &ModelPeripheral, address_s
         register_modules.push(code);
 
         // RegisterBlock entry
-        let mut s = String::new();
-        if let Some(description) = reg_info.description.as_ref() {
-            let doc = build_doc_comment("    ///", description);
-            s += &doc;
-        }
-        writeln!(s, "    pub {}: {}<{}>,", reg_info.name, access_type_name, size_type_name)?;
-        register_block.push(s);
+        let register_type = format!("{}<{}>", access_type_name, size_type_name);
+        let size = reg_info.properties.size / 8;
+        block_fields.push(BlockField {
+            name: reg_info.name.clone(),
+            description: reg_info.description.clone(),
+            offset: reg_info.address_offset,
+            layout: Layout { size: size * reg_info.dim.unwrap_or(1), align: size },
+            type_name: array_type(register_type, reg_info.dim),
+        });
 
         // ResetValues entry
-        let s = format!("    pub {}: {},", reg_info.name, size_type_name);
+        let s = format!("    pub {}: {},", reg_info.name, array_type(size_type_name.to_string(), reg_info.dim));
         reset_values.push(s);
     }
 
+    // Cluster modules live next to the register modules
+    let mut module_names: BTreeSet<&str> = registers.iter().map(|reg_info| reg_info.name.as_str()).collect();
+    for cluster in clusters {
+        if !module_names.insert(&cluster.module_name) {
+            bail!("{}: cluster module {} is already defined", context, cluster.module_name);
+        }
+
+        let context = format!("{}.{}", context, cluster.module_name);
+        let block = build_block(&context, &cluster.registers, &cluster.clusters, cluster.stride)?;
+
+        // Cluster module
+        let mut code = String::new();
+        if let Some(description) = cluster.description.as_ref() {
+            code += &build_doc_comment("///", description);
+        }
+        writeln!(code, "pub mod {} {{", cluster.module_name)?;
+        if !block.access_types.is_empty() {
+            writeln!(code, "    use crate::{{{}}};\n", block.access_types.join(", "))?;
+        }
+        writeln!(code, "{}", indent(&block.code, 1))?;
+        writeln!(code, "}}")?;
+        register_modules.push(code);
+
+        for field in &cluster.fields {
+            // RegisterBlock entry
+            let block_type = format!("{}::RegisterBlock", cluster.module_name);
+            block_fields.push(BlockField {
+                name: field.name.clone(),
+                description: cluster.description.clone(),
+                offset: field.address_offset,
+                layout: Layout { size: block.layout.size * field.dim.unwrap_or(1), align: block.layout.align },
+                type_name: array_type(block_type, field.dim),
+            });
+
+            // ResetValues entry
+            let values_type = format!("{}::ResetValues", cluster.module_name);
+            reset_values.push(format!("    pub {}: {},", field.name, array_type(values_type, field.dim)));
+        }
+    }
+
+    let (register_block, layout) = build_register_block("RegisterBlock", context, block_fields, stride)?;
+
+    let mut code = String::new();
+    writeln!(code, "{}", register_modules.join("\n"))?;
+    code += &register_block;
+    writeln!(code, "pub struct ResetValues {{")?;
+    writeln!(code, "{}", reset_values.join("\n"))?;
+    writeln!(code, "}}")?;
+
     let mut access_types: Vec<_> = access_types.iter().map(|s| s.to_string()).collect();
     access_types.sort();
-    writeln!(file, "use crate::{{{}}};", access_types.join(", "))?;
-    writeln!(file, "use core::marker::PhantomData;\n")?;
+    Ok(Block { access_types, code, layout })
+}
 
-    writeln!(file, "{}", register_modules.join("\n"))?;
+/// Field of a generated register block
+struct BlockField {
+    name: String,
+    description: Option<String>,
+    /// Offset from the start of the block, in bytes
+    offset: u32,
+    layout: Layout,
+    type_name: String,
+}
 
-    writeln!(file, "pub struct RegisterBlock {{")?;
-    write!(file, "{}", register_block.join("\n"))?;
-    writeln!(file, "}}\n")?;
+/// Lays out `fields` at their offsets, with reserved padding in between
+///
+/// Blocks used as array elements are padded to `stride`. Offsets are checked
+/// at compile time, so a layout that does not match the SVD fails to build
+/// instead of accessing the wrong addresses.
+fn build_register_block(struct_name: &str, context: &str, mut fields: Vec<BlockField>, stride: Option<u32>) -> Result<(String, Layout)> {
+    fields.sort_by_key(|field| field.offset);
+
+    let mut entries = Vec::new();
+    let mut asserts = Vec::new();
+    let mut offset = 0;
+    let mut align = 1;
+    let mut reserved = 0;
+    let mut previous: Option<&BlockField> = None;
+    for field in &fields {
+        if field.offset < offset {
+            let previous = previous.map(|field| field.name.as_str()).unwrap_or_default();
+            bail!("{}: {} at offset {:#x} overlaps {}", context, field.name, field.offset, previous);
+        }
+        if field.offset > offset {
+            entries.push(format!("    _reserved{}: [u8; {:#x}],\n", reserved, field.offset - offset));
+            reserved += 1;
+        }
 
-    writeln!(file, "pub struct ResetValues {{")?;
-    writeln!(file, "{}", reset_values.join("\n"))?;
-    writeln!(file, "}}")?;
+        let mut s = String::new();
+        if let Some(description) = field.description.as_ref() {
+            s += &build_doc_comment("    ///", description);
+        }
+        writeln!(s, "    pub {}: {},", field.name, field.type_name)?;
+        entries.push(s);
+        asserts.push(format!("    assert!(::core::mem::offset_of!({}, {}) == {:#x});", struct_name, field.name, field.offset));
 
-    writeln!(file, "
-pub struct Instance {{
-    pub(crate) addr: {},
-    pub(crate) _marker: PhantomData<*const RegisterBlock>,
-}}
+        offset = field.offset + field.layout.size;
+        align = align.max(field.layout.align);
+        previous = Some(field);
+    }
 
-impl ::core::ops::Deref for Instance {{
-    type Target = RegisterBlock;
-    #[inline(always)]
-    fn deref(&self) -> &RegisterBlock {{
-        unsafe {{ &*(self.addr as *const _) }}
-    }}
-}}", address_size.type_name())?;
+    if let Some(stride) = stride {
+        if offset > stride {
+            bail!("{}: registers end at offset {:#x}, beyond the array stride of {:#x}", context, offset, stride);
+        }
+        if offset < stride {
+            entries.push(format!("    _reserved{}: [u8; {:#x}],\n", reserved, stride - offset));
+        }
+        asserts.push(format!("    assert!(::core::mem::size_of::<{}>() == {:#x});", struct_name, stride));
+        offset = stride;
+    }
 
-    Ok(())
+    let mut code = String::new();
+    writeln!(code, "#[repr(C)]")?;
+    writeln!(code, "pub struct {} {{", struct_name)?;
+    write!(code, "{}", entries.join("\n"))?;
+    writeln!(code, "}}\n")?;
+    if !asserts.is_empty() {
+        writeln!(code, "const _: () = {{\n{}\n}};\n", asserts.join("\n"))?;
+    }
+
+    // Trailing padding of `repr(C)` structs
+    let size = offset.next_multiple_of(align);
+    Ok((code, Layout { size, align }))
 }
 
-fn write_peripheral_instance(file: &mut fs::File, instance: &ModelPeripheralInstance) -> Result<()> {
+fn write_peripheral_instance(file: &mut fs::File, instance: &ModelPeripheralInstance, peripheral_mod: &str) -> Result<()> {
     writeln!(file, "#![allow(non_snake_case, non_upper_case_globals)]")?;
     writeln!(file, "#![allow(non_camel_case_types)]")?;
     writeln!(file, "{}", build_doc_comment("//!", &instance.description))?;
 
-    let peripheral_mod = &instance.peripheral_module;
     writeln!(file, "pub use super::super::peripherals::{}::Instance;", peripheral_mod)?;
     writeln!(file, "pub use super::super::peripherals::{}::{{RegisterBlock, ResetValues}};", peripheral_mod)?;
 
     let mut register_types = Vec::new();
     for value in &instance.reset_values {
-        register_types.push(value.register.clone());
+        let module = match value {
+            ResetValue::Register { name, .. } => name,
+            ResetValue::Cluster { module_name, .. } => module_name,
+        };
+        if !register_types.contains(module) {
+            register_types.push(module.clone());
+        }
     }
 
     if !register_types.is_empty() {
@@ -250,10 +392,7 @@ pub mod {name} {{
         instance.base_address, name=instance.name
     )?;
 
-    let mut values = Vec::new();
-    for value in &instance.reset_values {
-        values.push(format!("        {}: {:#x},", value.register, value.value));
-    }
+    let values = build_reset_values(&instance.reset_values, "super", 2);
     write!(file, "{}", values.join("\n"))?;
 
     writeln!(file, "
@@ -343,6 +482,38 @@ pub const {name}: *const RegisterBlock = {:#x} as *const _;",
     Ok(())
 }
 
+/// Lines of a `ResetValues` initializer, `path` leads to the register modules
+fn build_reset_values(values: &[ResetValue], path: &str, level: usize) -> Vec<String> {
+    let prefix = "    ".repeat(level);
+    let mut lines = Vec::new();
+    for value in values {
+        match value {
+            ResetValue::Register { name, value, dim: Some(dim) } => lines.push(format!("{}{}: [{:#x}; {}],", prefix, name, value, dim)),
+            ResetValue::Register { name, value, dim: None } => lines.push(format!("{}{}: {:#x},", prefix, name, value)),
+            ResetValue::Cluster { name, module_name, values, dim } => {
+                let path = format!("{}::{}", path, module_name);
+                let (open, level) = match dim {
+                    Some(_) => {
+                        lines.push(format!("{}{}: [", prefix, name));
+                        (format!("{}    {}::ResetValues {{", prefix, path), level + 1)
+                    }
+                    None => (format!("{}{}: {}::ResetValues {{", prefix, name, path), level),
+                };
+                let close = format!("{}}},", "    ".repeat(level));
+                for _ in 0..dim.unwrap_or(1) {
+                    lines.push(open.clone());
+                    lines.extend(build_reset_values(values, &path, level + 1));
+                    lines.push(close.clone());
+                }
+                if dim.is_some() {
+                    lines.push(format!("{}],", prefix));
+                }
+            }
+        }
+    }
+    lines
+}
+
 trait Codegen {
     fn generate_code(&self) -> String;
 }
@@ -366,14 +537,11 @@ impl Codegen for FinalFieldInfo {
     pub const mask: u32 = {:#x} << offset;
 ", self.bit_range.width, mask, self.bit_range.offset, mask).unwrap();
 
-        writeln!(code, "
-    /// Read-only values (empty)
-    pub mod R {{}}
-    /// Write-only values (empty)
-    pub mod W {{}}
-    /// Read-write values (empty)
-    pub mod RW {{}}
-").unwrap();
+        writeln!(code).unwrap();
+        code += &build_values_module("R", "Read-only values", &self.read_values, self.bit_range.width);
+        code += &build_values_module("W", "Write-only values", &self.write_values, self.bit_range.width);
+        code += &build_values_module("RW", "Read-write values", &self.read_write_values, self.bit_range.width);
+        writeln!(code).unwrap();
 
         writeln!(code, "}}").unwrap();
 
@@ -381,6 +549,43 @@ impl Codegen for FinalFieldInfo {
     }
 }
 
+/// Generates the `R`, `W` or `RW` module with the named values of a field
+fn build_values_module(name: &str, doc: &str, values: &[FinalEnumeratedValue], width: u32) -> String {
+    let mut code = String::new();
+    if values.is_empty() {
+        writeln!(code, "    /// {} (empty)", doc).unwrap();
+        writeln!(code, "    pub mod {} {{}}", name).unwrap();
+        return code;
+    }
+
+    writeln!(code, "    /// {}", doc).unwrap();
+    writeln!(code, "    pub mod {} {{", name).unwrap();
+    for value in values {
+        // Binary literals as wide as the field, unless that gets unwieldy
+        let literal = if width <= 8 {
+            format!("{:#0width$b}", value.value, width = width as usize + 2)
+        } else {
+            format!("{:#x}", value.value)
+        };
+        let description = match value.description.as_ref() {
+            Some(description) => format!("{}: {}", literal, description),
+            None => literal.clone(),
+        };
+        code += &indent(&build_doc_comment("///", &description), 2);
+        writeln!(code, "\n        pub const {}: u32 = {};", value.name, literal).unwrap();
+    }
+    writeln!(code, "    }}").unwrap();
+    code
+}
+
+/// Type of an indexed register array, or of a single register
+fn array_type(type_name: String, dim: Option<u32>) -> String {
+    match dim {
+        Some(dim) => format!("[{}; {}]", type_name, dim),
+        None => type_name,
+    }
+}
+
 fn build_doc_comment(prefix: &str, doc: &str) -> String {
     let mut doc_string = String::new();
     for line in doc.lines() {
@@ -398,4 +603,264 @@ fn indent(s: &str, levels: usize) -> String {
     }
 
     lines.join("\n")
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    const SVD: &str = include_str!("test.svd");
+
+    fn build_peripheral_block(svd: &str, name: &str) -> Result<Block> {
+        let device = svd_parser::parse(svd)?;
+        let device = crate::convert::convert(&device, &[])?;
+        let peripheral = device.peripherals.iter().find(|peripheral| peripheral.name == name).unwrap();
+        build_block(&peripheral.name, &peripheral.registers, &peripheral.clusters, None)
+    }
+
+    fn peripheral_block(name: &str) -> Block {
+        build_peripheral_block(SVD, name).unwrap()
+    }
+
+    /// Generates the test SVD and returns some of the generated files
+    fn generated_files(test: &str, paths: &[&str]) -> Vec<String> {
+        let output_dir = std::env::temp_dir().join(format!("svd2ral-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&output_dir).unwrap();
+        generate(SVD, &output_dir, AddressSize::U32, &[]).unwrap();
+        let contents = paths.iter().map(|path| fs::read_to_string(output_dir.join("test").join(path)).unwrap()).collect();
+        fs::remove_dir_all(&output_dir).unwrap();
+        contents
+    }
+
+    fn generated_file(test: &str, path: &str) -> String {
+        generated_files(test, &[path]).remove(0)
+    }
+
+    #[test]
+    fn register_arrays() {
+        let block = peripheral_block("ARR");
+
+        // Packed arrays numbered from 0 become Rust arrays with one register module
+        assert!(block.code.contains("/// Channel n\npub mod CH {"));
+        assert!(block.code.contains("pub CH: [RWRegister<u32>; 4],"));
+        assert!(block.code.contains("pub CH: [u32; 4],"));
+
+        // Arrays with named elements or gaps are expanded
+        for name in &["PORTA", "PORTB", "PORTC", "WIDE0", "WIDE1"] {
+            assert!(block.code.contains(&format!("pub mod {} {{", name)), "{}", name);
+            assert!(block.code.contains(&format!("pub {}: RWRegister<u32>,", name)), "{}", name);
+        }
+        assert!(block.code.contains("/// Port B\npub mod PORTB {"));
+        assert!(block.code.contains("offset_of!(RegisterBlock, PORTB) == 0x14"));
+        assert!(block.code.contains("offset_of!(RegisterBlock, WIDE1) == 0x28"));
+        assert!(block.code.contains("_reserved1: [u8; 0x4],"));
+        assert_eq!(block.layout.size, 0x2c);
+    }
+
+    /// Crate root for the generated test SVD, with a stand-in for `cortex_m`
+    const MACROS_LIB_RS: &str = r#"
+#![no_std]
+
+pub mod arch {
+    pub mod interrupt {
+        pub fn free<R>(f: impl FnOnce(&()) -> R) -> R {
+            f(&())
+        }
+    }
+}
+
+#[macro_use]
+pub mod register;
+pub use crate::register::{RORegister, UnsafeRORegister};
+pub use crate::register::{WORegister, UnsafeWORegister};
+pub use crate::register::{RWRegister, UnsafeRWRegister};
+
+pub mod test;
+
+pub fn indexed_registers(index: usize) -> u32 {
+    let arr = test::arr::ARR::take().unwrap();
+    write_reg!(test::arr, arr, CH[1], EN: 1, MODE: 2);
+    write_reg!(test::arr, arr, CH[index], 0x5);
+    modify_reg!(test::arr, arr, CH[2], MODE: 3);
+    modify_reg!(test::arr, arr, CH[index], |value| value | 1);
+    reset_reg!(test::arr, arr, ARR, CH[3]);
+    reset_reg!(test::arr, arr, ARR, CH[index], MODE);
+    reset_reg!(test::arr, arr, &test::arr::ARR::reset, CH[0], EN);
+    let (en, mode) = read_reg!(test::arr, arr, CH[1], EN, MODE);
+    let enabled = read_reg!(test::arr, arr, CH[index], EN == 1);
+    en + mode + enabled as u32 + read_reg!(test::arr, arr, CH[index])
+}
+"#;
+
+    #[test]
+    fn register_array_macros() {
+        // The macros index arrays with `$reg[$index]`, a change that breaks
+        // this fails to compile
+        let output_dir = std::env::temp_dir().join(format!("svd2ral-macros-{}", std::process::id()));
+        fs::create_dir_all(&output_dir).unwrap();
+        generate(SVD, &output_dir, AddressSize::U32, &[]).unwrap();
+        fs::write(output_dir.join("lib.rs"), MACROS_LIB_RS).unwrap();
+        let output = Command::new("rustc")
+            .args(["--edition", "2018", "--crate-type", "lib", "--crate-name", "macros", "--emit", "metadata", "--out-dir"])
+            .arg(&output_dir)
+            .arg(output_dir.join("lib.rs"))
+            .output()
+            .unwrap();
+        fs::remove_dir_all(&output_dir).unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[test]
+    fn register_array_reset_values() {
+        let instance = generated_file("arrays", "instances/arr.rs");
+        assert!(instance.contains("pub use super::super::peripherals::arr::{CH, PORTA, PORTB, PORTC, WIDE0, WIDE1};"));
+        assert!(instance.contains("        CH: [0x5; 4],\n        PORTA: 0x0,"));
+        assert!(instance.contains("        WIDE1: 0x1,"));
+    }
+
+    #[test]
+    fn clusters() {
+        let block = peripheral_block("DMA");
+
+        // Indexed cluster arrays are padded to their stride
+        assert!(block.code.contains("pub CH: [CH::RegisterBlock; 2],"));
+        assert!(block.code.contains("        _reserved2: [u8; 0x10],\n    }"));
+        assert!(block.code.contains("assert!(::core::mem::size_of::<RegisterBlock>() == 0x20);"));
+        assert!(block.code.contains("offset_of!(RegisterBlock, CFG) == 0xc"));
+
+        // Nested clusters get their own module inside the parent cluster
+        assert!(block.code.contains("/// Channel n\npub mod CH {\n    use crate::{RWRegister};"));
+        assert!(block.code.contains("    pub mod CFG {\n        use crate::{RWRegister};"));
+
+        // Other cluster arrays are expanded and share one module
+        assert_eq!(block.code.matches("pub mod BANK {").count(), 1);
+        assert!(block.code.contains("pub BANKA: BANK::RegisterBlock,"));
+        assert!(block.code.contains("offset_of!(RegisterBlock, BANKB) == 0x70"));
+        assert!(block.code.contains("pub CH: [CH::ResetValues; 2],"));
+        assert_eq!(block.layout.size, 0x78);
+    }
+
+    #[test]
+    fn cluster_reset_values() {
+        let instance = generated_file("clusters", "instances/dma.rs");
+        assert!(instance.contains("pub use super::super::peripherals::dma::{ISR, CH, BANK};"));
+        let channel = "
+            super::CH::ResetValues {
+                CR: 0x3,
+                CNT: 0x0,
+                CFG: super::CH::CFG::ResetValues {
+                    SEL: 0x7,
+                },
+            },";
+        assert!(instance.contains(&format!("        CH: [{}{}\n        ],", channel, channel)));
+        assert!(instance.contains("        BANKB: super::BANK::ResetValues {\n            IN: 0x0,\n            OUT: 0x9,\n        },"));
+    }
+
+    #[test]
+    fn cluster_errors() {
+        let svd = SVD.replace("<name>CFG</name>", "<name>CR</name>");
+        let error = build_peripheral_block(&svd, "DMA").err().unwrap();
+        assert_eq!(error.to_string(), "DMA.CH: cluster module CR is already defined");
+
+        let svd = SVD.replace("<dimIncrement>0x20</dimIncrement>", "<dimIncrement>0x8</dimIncrement>");
+        let error = build_peripheral_block(&svd, "DMA").err().unwrap();
+        assert_eq!(error.to_string(), "DMA.CH: registers end at offset 0x10, beyond the array stride of 0x8");
+    }
+
+    #[test]
+    fn layout_gaps() {
+        // 16-bit CNT at 0x8 leaves two bytes in front of CFG
+        let block = peripheral_block("DMA");
+        assert!(block.code.contains("        _reserved1: [u8; 0x2],\n"));
+        assert!(block.code.contains("        assert!(::core::mem::offset_of!(RegisterBlock, CFG) == 0xc);"));
+
+        // Byte registers can leave odd gaps
+        let data = "<name>DATA</name>\n          <description>Data</description>\n          <addressOffset>0x4</addressOffset>";
+        let svd = SVD.replacen(data, &data.replace("0x4</addressOffset>", "0x5</addressOffset>\n          <size>8</size>"), 1);
+        let block = build_peripheral_block(&svd, "UART0").unwrap();
+        assert!(block.code.contains("    _reserved0: [u8; 0x1],\n"));
+        assert!(block.code.contains("    pub DATA: RWRegister<u8>,\n"));
+        assert!(block.code.contains("    assert!(::core::mem::offset_of!(RegisterBlock, DATA) == 0x5);"));
+        assert_eq!(block.layout.size, 0x8);
+    }
+
+    #[test]
+    fn overlapping_registers() {
+        let data = "<name>DATA</name>\n          <description>Data</description>\n          <addressOffset>0x4</addressOffset>";
+        let svd = SVD.replacen(data, &data.replace("0x4", "0x0"), 1);
+        let error = build_peripheral_block(&svd, "UART0").err().unwrap();
+        assert_eq!(error.to_string(), "UART0: DATA at offset 0x0 overlaps CTRL");
+    }
+
+    #[test]
+    fn shared_peripheral_modules() {
+        let files = generated_files("derived", &["peripherals/mod.rs", "metadata.rs", "instances/uart2.rs", "instances/uart3.rs"]);
+        let (peripherals, metadata, uart2, uart3) = (&files[0], &files[1], &files[2], &files[3]);
+
+        // Derived and structurally identical peripherals use the module of the first one
+        assert!(peripherals.contains("pub mod uart0;\n"));
+        assert!(!peripherals.contains("uart1") && !peripherals.contains("uart2") && !peripherals.contains("uart3"));
+        assert_eq!(metadata.matches("\"uart0\",").count(), 2);
+        assert!(metadata.contains("\"uart0\",\n    \"uart1\",\n    \"uart2\",\n    \"uart3\","));
+        for instance in &[uart2, uart3] {
+            assert!(instance.contains("pub use super::super::peripherals::uart0::{RegisterBlock, ResetValues};"));
+            assert!(instance.contains("pub use super::super::peripherals::uart0::{CTRL, DATA};"));
+        }
+
+        // Instances keep their own address, reset values and description
+        assert!(uart2.contains("//! Serial port\n"));
+        assert!(uart2.contains("addr: 0x10002200,"));
+        assert!(uart2.contains("        CTRL: 0x1,"));
+        assert!(uart3.contains("//! Serial port with another reset value\n"));
+        assert!(uart3.contains("        CTRL: 0x7,"));
+    }
+
+    #[test]
+    fn derivation_errors() {
+        let svd = SVD.replace("derivedFrom=\"UART1\"", "derivedFrom=\"UART9\"");
+        let error = build_peripheral_block(&svd, "UART0").err().unwrap();
+        assert_eq!(error.to_string(), "UART2: derived from unknown peripheral UART9");
+
+        let svd = SVD.replace("<peripheral>\n      <name>UART0</name>", "<peripheral derivedFrom=\"UART2\">\n      <name>UART0</name>");
+        let error = build_peripheral_block(&svd, "UART0").err().unwrap();
+        assert_eq!(error.to_string(), "UART0: circular derivedFrom");
+    }
+
+    #[test]
+    fn enumerated_values() {
+        let block = peripheral_block("TIMER");
+
+        // Values without a usage are read-write, default values are left out
+        assert!(block.code.contains("        /// Read-write values
+        pub mod RW {
+            /// 0b00: Normal operation
+            pub const Normal: u32 = 0b00;
+            /// 0b01: Stop after one period
+            pub const OneShot: u32 = 0b01;
+            /// 0b10
+            pub const _2x: u32 = 0b10;
+        }"));
+        assert!(!block.code.contains("Reserved"));
+
+        // Values derived from another field are copied, wide fields use hex
+        assert_eq!(block.code.matches("pub const OneShot: u32 = 0b01;").count(), 2);
+        assert!(block.code.contains("/// 0x1000: Divide by 4096\n            pub const Slow: u32 = 0x1000;"));
+
+        // Read and write values go into their own modules
+        assert!(block.code.contains("        pub mod R {\n            /// 0b0: Idle\n            pub const Idle: u32 = 0b0;"));
+        assert!(block.code.contains("        pub mod W {\n            /// 0b1: Stop counting\n            pub const Stop: u32 = 0b1;\n        }"));
+        assert!(block.code.contains("        /// Read-write values (empty)\n        pub mod RW {}"));
+    }
+
+    #[test]
+    fn field_errors() {
+        let svd = SVD.replace("derivedFrom=\"CTRL.modes\"", "derivedFrom=\"CTRL.speeds\"");
+        let error = build_peripheral_block(&svd, "TIMER").err().unwrap();
+        assert_eq!(error.to_string(), "ALT: enumeratedValues derived from unknown CTRL.speeds");
+
+        let svd = SVD.replace("<access>read-write</access>", "");
+        let error = build_peripheral_block(&svd, "TIMER").err().unwrap();
+        assert_eq!(error.to_string(), "CH.EN: no access");
+    }
+}
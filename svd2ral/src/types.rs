@@ -10,6 +10,26 @@ pub struct ModelPeripheral {
     pub description: String,
     pub module_name: String,
     pub registers: Vec<FinalRegisterInfo>,
+    pub clusters: Vec<ModelCluster>,
+}
+
+/// Cluster of registers, generated as a nested register block
+pub struct ModelCluster {
+    pub module_name: String,
+    pub description: Option<String>,
+    pub registers: Vec<FinalRegisterInfo>,
+    pub clusters: Vec<ModelCluster>,
+    /// Fields of the parent block that hold this cluster
+    pub fields: Vec<ClusterField>,
+    /// Size the block is padded to, so it can be an array element
+    pub stride: Option<u32>,
+}
+
+pub struct ClusterField {
+    pub name: String,
+    pub address_offset: u32,
+    /// Number of elements if the cluster is an indexed array
+    pub dim: Option<u32>,
 }
 
 pub struct ModelPeripheralInstance {
@@ -21,9 +41,20 @@ pub struct ModelPeripheralInstance {
     pub reset_values: Vec<ResetValue>,
 }
 
-pub struct ResetValue {
-    pub register: String,
-    pub value: u64,
+pub enum ResetValue {
+    Register {
+        name: String,
+        value: u64,
+        /// Number of elements if the register is an indexed array
+        dim: Option<u32>,
+    },
+    Cluster {
+        name: String,
+        module_name: String,
+        values: Vec<ResetValue>,
+        /// Number of elements if the cluster is an indexed array
+        dim: Option<u32>,
+    },
 }
 
 pub struct FinalFieldInfo {
@@ -31,12 +62,24 @@ pub struct FinalFieldInfo {
     pub description: Option<String>,
     pub bit_range: BitRange,
     pub access: Access,
+    /// Values from `enumeratedValues`, by `usage`
+    pub read_values: Vec<FinalEnumeratedValue>,
+    pub write_values: Vec<FinalEnumeratedValue>,
+    pub read_write_values: Vec<FinalEnumeratedValue>,
+}
+
+pub struct FinalEnumeratedValue {
+    pub name: String,
+    pub description: Option<String>,
+    pub value: u32,
 }
 
 pub struct FinalRegisterInfo {
     pub name: String,
     pub description: Option<String>,
     pub address_offset: u32,
+    /// Number of elements if the register is an indexed array
+    pub dim: Option<u32>,
     pub properties: FinalRegisterProperties,
     pub fields: Vec<FinalFieldInfo>,
 }